@@ -0,0 +1,52 @@
+// Anchored memchr scan against testing every position, over a buffer the size of a game executable
+// Its unit tests get compiled in here too but never run without the test harness
+#[path = "../src/pattern_helpers.rs"]
+#[allow(dead_code)]
+mod pattern_helpers;
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+use pattern_helpers::Signature;
+
+const SIZE: usize = 128 * 1024 * 1024;
+const RUNS: usize = 3;
+
+// Roughly the byte distribution of x86-64 code: mostly opcode and modrm bytes, some immediates
+fn code_like_buffer(size: usize) -> Vec<u8> {
+    const COMMON: &[u8] = &[0x00, 0xFF, 0xCC, 0x48, 0x8B, 0x89, 0x0F, 0x24, 0x44, 0x4C, 0x83, 0x01, 0x8D, 0xE8, 0x85, 0xC0];
+    let mut state = 0x9E37_79B9_7F4A_7C15u64;
+    (0..size).map(|_| {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        if state % 10 < 7 { COMMON[(state >> 8) as usize % COMMON.len()] } else { (state >> 16) as u8 }
+    }).collect()
+}
+
+fn naive(signature: &Signature, data: &[u8]) -> Option<usize> {
+    (0..=data.len() - signature.len()).find(|&i| signature.matches_at(data, i)).map(|i| i + signature.offset)
+}
+
+fn best_of(f: impl Fn() -> Option<usize>) -> (Duration, Option<usize>) {
+    (0..RUNS).map(|_| {
+        let started = Instant::now();
+        let result = black_box(f());
+        (started.elapsed(), result)
+    }).min_by_key(|(elapsed, _)| *elapsed).unwrap()
+}
+
+fn main() {
+    let mut data = code_like_buffer(SIZE);
+    // Real hit close to the end, so both scans walk the whole buffer
+    let hit = SIZE - 4096;
+    data[hit..hit + 10].copy_from_slice(&[0xB9, 0x3C, 0x00, 0x00, 0x00, 0xE8, 0x12, 0x34, 0x56, 0x78]);
+    println!("Scanning {} MB", SIZE / 1024 / 1024);
+
+    for pattern in ["B9 3C 00 00 00 [E8] ?? ?? ?? ??", "7F 0F [8B] 05 ?? ?? ?? ??", "83 F9 04 77 ?? [89] 0D ?? ?? ?? ??"] {
+        let signature = Signature::parse(pattern).unwrap();
+        let (anchored_time, anchored) = best_of(|| signature.find(&data));
+        let (naive_time, expected) = best_of(|| naive(&signature, &data));
+        assert_eq!(anchored, expected, "{}", pattern);
+        println!("{:<36} anchored {:>8.1} ms | naive {:>8.1} ms | {:.1}x | {:?}", pattern, anchored_time.as_secs_f64() * 1000.0, naive_time.as_secs_f64() * 1000.0, naive_time.as_secs_f64() / anchored_time.as_secs_f64(), anchored);
+    }
+}
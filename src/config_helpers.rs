@@ -0,0 +1,98 @@
+use std::collections::BTreeMap;
+use std::io::{Error, ErrorKind};
+use std::path::PathBuf;
+use std::time::Duration;
+use serde::{Deserialize, Serialize};
+use crate::display_helpers::FpsTarget;
+use crate::wine_helpers::xdg_dir;
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct GameProfile {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target_fps: Option<FpsTarget>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub refresh_delay: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub game_path: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prefix_path: Option<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub options: BTreeMap<String, String>
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Config {
+    #[serde(default)]
+    pub games: BTreeMap<String, GameProfile>
+}
+
+impl GameProfile {
+    // Stored as milliseconds to stay readable by older versions, a hand edited 0 counts as unset
+    pub fn refresh(&self) -> Option<Duration> { self.refresh_delay.filter(|ms| *ms > 0).map(Duration::from_millis) }
+
+    pub fn get(&self, key: &str) -> Option<String> {
+        match key {
+            "target_fps" => self.target_fps.map(|v| v.to_string()),
+            "refresh_delay" => self.refresh_delay.map(|v| v.to_string()),
+            "game_path" => self.game_path.clone(),
+            "prefix_path" => self.prefix_path.clone(),
+            _ => self.options.get(key).cloned()
+        }
+    }
+
+    pub fn set(&mut self, key: &str, value: &str) -> std::io::Result<()> {
+        match key {
+            "target_fps" => self.target_fps = Some(value.parse()?),
+            "refresh_delay" => self.refresh_delay = Some(parse_duration(value)?.as_millis() as u64),
+            "game_path" => self.game_path = Some(value.to_string()),
+            "prefix_path" => self.prefix_path = Some(value.to_string()),
+            _ => { self.options.insert(key.to_string(), value.to_string()); }
+        }
+        Ok(())
+    }
+}
+
+// The deprecated positional refresh_delay, where scripts pass 0 for the default delay, comes through as zero for the caller to replace
+pub fn parse_legacy_refresh(value: &str) -> std::io::Result<Duration> {
+    if value.trim() == "0" { return Ok(Duration::ZERO); }
+    parse_duration(value)
+}
+
+// "500ms", "1.5s", "2m" or "1h", bare numbers are milliseconds like the old refresh_delay argument
+pub fn parse_duration(value: &str) -> std::io::Result<Duration> {
+    let invalid = || Error::new(ErrorKind::InvalidInput, format!("Invalid duration \"{}\", expected e.g. 500ms, 2s or 1m!", value));
+    let value = value.trim();
+    let split = value.find(|c: char| !(c.is_ascii_digit() || c == '.')).unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let number: f64 = number.parse().map_err(|_| invalid())?;
+    let millis = match unit.trim() {
+        "" | "ms" => 1.0,
+        "s" | "sec" | "secs" => 1000.0,
+        "m" | "min" | "mins" => 60_000.0,
+        "h" | "hr" | "hrs" => 3_600_000.0,
+        _ => return Err(invalid())
+    };
+    let duration = Duration::from_millis((number * millis).round() as u64);
+    // A zero delay would spin on the game's memory
+    if duration.is_zero() { return Err(Error::new(ErrorKind::InvalidInput, format!("Duration \"{}\" is too short, use at least 1ms!", value))); }
+    Ok(duration)
+}
+
+// $XDG_CONFIG_HOME/keqing_unlock/config.json on Linux (also when running under Wine), %APPDATA%\keqing_unlock\config.json on Windows
+pub fn config_path() -> std::io::Result<PathBuf> {
+    let Some(base) = xdg_dir("XDG_CONFIG_HOME", ".config") else { return Err(Error::new(ErrorKind::NotFound, "Could not determine config directory!")) };
+    Ok(base.join("keqing_unlock").join("config.json"))
+}
+
+pub fn load_config() -> std::io::Result<Config> {
+    let path = config_path()?;
+    if !path.exists() { return Ok(Config::default()); }
+    let content = std::fs::read_to_string(path)?;
+    serde_json::from_str(&content).map_err(|e| Error::new(ErrorKind::InvalidData, format!("Failed to parse config: {}", e)))
+}
+
+pub fn save_config(config: &Config) -> std::io::Result<()> {
+    let path = config_path()?;
+    if let Some(parent) = path.parent() { std::fs::create_dir_all(parent)?; }
+    std::fs::write(path, serde_json::to_string_pretty(config)?)
+}
@@ -0,0 +1,403 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Error, ErrorKind, Read, Write};
+use std::os::windows::io::{AsRawHandle, FromRawHandle};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, Weak};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::JoinHandle;
+use std::time::Duration;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use windows::core::{PCSTR, PCWSTR, PSTR};
+use windows::Win32::Foundation::{CloseHandle, ERROR_PIPE_CONNECTED, HANDLE};
+use windows::Win32::Networking::WinSock::{accept, bind, closesocket, listen, recv, send, socket, WSAGetLastError, WSAStartup, AF_UNIX, INVALID_SOCKET, SEND_RECV_FLAGS, SOCKADDR, SOCKET, SOCKET_ERROR, SOCK_STREAM, WSAData};
+use windows::Win32::Storage::FileSystem::PIPE_ACCESS_DUPLEX;
+use windows::Win32::System::Pipes::{ConnectNamedPipe, CreateNamedPipeW, PeekNamedPipe, PIPE_READMODE_BYTE, PIPE_REJECT_REMOTE_CLIENTS, PIPE_TYPE_BYTE, PIPE_UNLIMITED_INSTANCES, PIPE_WAIT};
+use crate::config_helpers::parse_duration;
+use crate::display_helpers::FpsTarget;
+use crate::event_helpers::{Event, EventSink};
+use crate::game_helpers::{find_game, Game, Unlocker, GAMES};
+use crate::hk4e_helpers::{get_pid_from_handle, wait_for_exit_or_cancel, wait_for_process, WaitOptions};
+use crate::options_helpers::{resolve_run_options, RunRequest};
+use crate::unlock_helpers::{apply_bh3, apply_hk4e, apply_hkrpg, apply_wuwa, backup_startup, restore_startup, StartupBackup};
+use crate::wine_helpers::{is_wine, unix_path};
+
+pub const DEFAULT_PIPE: &str = r"\\.\pipe\keqing_unlock";
+
+// JSON-RPC 2.0 error codes
+const PARSE_ERROR: i64 = -32700;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const SERVER_ERROR: i64 = -32000;
+
+pub enum Endpoint {
+    Pipe(String),
+    Socket(PathBuf)
+}
+
+// Native launchers cannot open Wine's named pipes, but Wine maps AF_UNIX sockets onto the host
+pub fn default_endpoint() -> Endpoint {
+    if !is_wine() { return Endpoint::Pipe(DEFAULT_PIPE.to_string()); }
+    let dir = std::env::var("XDG_RUNTIME_DIR").ok().filter(|d| d.starts_with('/')).unwrap_or("/tmp".to_string());
+    Endpoint::Socket(unix_path(&format!("{}/keqing_unlock.sock", dir)))
+}
+
+pub fn run_daemon(endpoint: Endpoint) -> std::io::Result<()> {
+    let daemon = Arc::new(Daemon::default());
+    match endpoint {
+        Endpoint::Pipe(name) => serve_pipe(&name, daemon),
+        Endpoint::Socket(path) => serve_socket(&path, daemon)
+    }
+}
+
+// A connected client. Requests are read on its own thread while sessions write events to it, so both only take &self
+trait Stream: Send + Sync {
+    fn read(&self, buf: &mut [u8]) -> std::io::Result<usize>;
+    fn write_all(&self, buf: &[u8]) -> std::io::Result<()>;
+}
+
+const PIPE_POLL: Duration = Duration::from_millis(20);
+
+struct PipeStream(File);
+
+impl Stream for PipeStream {
+    // Synchronous pipe I/O is serialized per handle, a blocking read would hold up every event write until the next request
+    fn read(&self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let handle = HANDLE(self.0.as_raw_handle() as isize);
+        loop {
+            let mut available = 0;
+            // Fails once the client hung up, which is the end of its requests
+            if !unsafe { PeekNamedPipe(handle, std::ptr::null_mut(), 0, std::ptr::null_mut(), &mut available, std::ptr::null_mut()) }.as_bool() { return Ok(0); }
+            if available > 0 { return (&self.0).read(buf); }
+            std::thread::sleep(PIPE_POLL);
+        }
+    }
+
+    fn write_all(&self, buf: &[u8]) -> std::io::Result<()> { (&self.0).write_all(buf) }
+}
+
+struct SocketStream(SOCKET);
+
+impl Stream for SocketStream {
+    fn read(&self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = unsafe { recv(self.0, PSTR(buf.as_mut_ptr()), buf.len().min(i32::MAX as usize) as i32, SEND_RECV_FLAGS(0)) };
+        if read == SOCKET_ERROR { Err(wsa_error()) } else { Ok(read as usize) }
+    }
+
+    fn write_all(&self, mut buf: &[u8]) -> std::io::Result<()> {
+        while !buf.is_empty() {
+            let sent = unsafe { send(self.0, PCSTR(buf.as_ptr()), buf.len().min(i32::MAX as usize) as i32, SEND_RECV_FLAGS(0)) };
+            if sent == SOCKET_ERROR { return Err(wsa_error()); }
+            buf = &buf[sent as usize..];
+        }
+        Ok(())
+    }
+}
+
+impl Drop for SocketStream {
+    fn drop(&mut self) { unsafe { closesocket(self.0); } }
+}
+
+fn wsa_error() -> Error { Error::from_raw_os_error(unsafe { WSAGetLastError() }.0) }
+
+fn serve_pipe(name: &str, daemon: Arc<Daemon>) -> std::io::Result<()> {
+    let wide: Vec<u16> = name.encode_utf16().chain(std::iter::once(0)).collect();
+    println!("Listening on {}", name);
+    loop {
+        let handle = unsafe { CreateNamedPipeW(PCWSTR(wide.as_ptr()), PIPE_ACCESS_DUPLEX, PIPE_TYPE_BYTE | PIPE_READMODE_BYTE | PIPE_WAIT | PIPE_REJECT_REMOTE_CLIENTS, PIPE_UNLIMITED_INSTANCES, 64 * 1024, 64 * 1024, 0, std::ptr::null()) };
+        if handle.is_invalid() { return Err(Error::last_os_error()); }
+        // Clients that connect before ConnectNamedPipe is called are reported as ERROR_PIPE_CONNECTED
+        let connected = unsafe { ConnectNamedPipe(handle, std::ptr::null_mut()) }.as_bool() || Error::last_os_error().raw_os_error() == Some(ERROR_PIPE_CONNECTED.0 as i32);
+        if !connected { unsafe { CloseHandle(handle); } continue; }
+        spawn_client(Box::new(PipeStream(unsafe { File::from_raw_handle(handle.0 as _) })), daemon.clone());
+    }
+}
+
+// sockaddr_un, not part of the Winsock bindings
+#[repr(C)]
+struct SockaddrUn {
+    family: u16,
+    path: [u8; 108]
+}
+
+fn serve_socket(path: &Path, daemon: Arc<Daemon>) -> std::io::Result<()> {
+    let mut data = WSAData::default();
+    if unsafe { WSAStartup(0x0202, &mut data) } != 0 { return Err(Error::other("Failed to initialize Winsock!")); }
+    let name = path.to_string_lossy();
+    let mut address = SockaddrUn { family: AF_UNIX, path: [0; 108] };
+    if name.len() >= address.path.len() { return Err(Error::new(ErrorKind::InvalidInput, format!("Socket path {} is too long!", name))); }
+    address.path[..name.len()].copy_from_slice(name.as_bytes());
+    // A socket file left behind by a previous run makes bind fail
+    let _ = std::fs::remove_file(path);
+
+    let listener = unsafe { socket(AF_UNIX as i32, SOCK_STREAM as i32, 0) };
+    if listener == INVALID_SOCKET { return Err(wsa_error()); }
+    let listener = SocketStream(listener);
+    if unsafe { bind(listener.0, &address as *const _ as *const SOCKADDR, size_of::<SockaddrUn>() as i32) } == SOCKET_ERROR { return Err(wsa_error()); }
+    if unsafe { listen(listener.0, 8) } == SOCKET_ERROR { return Err(wsa_error()); }
+    println!("Listening on {}", path.display());
+    loop {
+        let client = unsafe { accept(listener.0, std::ptr::null_mut(), std::ptr::null_mut()) };
+        if client == INVALID_SOCKET { eprintln!("Failed to accept client: {}!", wsa_error()); continue; }
+        spawn_client(Box::new(SocketStream(client)), daemon.clone());
+    }
+}
+
+struct Client {
+    stream: Box<dyn Stream>,
+    // Responses and events come from different threads, lines must not interleave
+    writing: Mutex<()>
+}
+
+impl Client {
+    fn send(&self, message: &Value) {
+        let mut line = message.to_string();
+        line.push('\n');
+        let _guard = self.writing.lock().unwrap();
+        // A client that went away simply stops getting messages
+        let _ = self.stream.write_all(line.as_bytes());
+    }
+}
+
+struct ClientReader<'a>(&'a Client);
+
+impl Read for ClientReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> { self.0.stream.read(buf) }
+}
+
+fn spawn_client(stream: Box<dyn Stream>, daemon: Arc<Daemon>) {
+    std::thread::spawn(move || daemon.serve(Arc::new(Client { stream, writing: Mutex::new(()) })));
+}
+
+#[derive(Debug, Deserialize)]
+struct Request {
+    // Notifications come without an id and get no response
+    #[serde(default)]
+    id: Option<Value>,
+    method: String,
+    #[serde(default)]
+    params: Value
+}
+
+#[derive(Debug, Deserialize)]
+struct GameParams {
+    game_id: String
+}
+
+// Same options as the run command, anything omitted falls back to the config profile
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct ApplyParams {
+    game_id: String,
+    fps: Option<FpsTarget>,
+    refresh: Option<String>,
+    path: Option<String>,
+    pid: Option<u32>,
+    parent_pid: Option<u32>,
+    timeout: Option<u64>,
+    disable_vsync: bool,
+    keep_fps_unfocused: bool,
+    fov: Option<f32>,
+    unfocused_fps: Option<u32>,
+    schedule: Option<String>,
+    battery_fps: Option<u32>,
+    battery_thresholds: Option<String>
+}
+
+enum SessionKind {
+    // Genshin, patched in memory for as long as the game runs
+    Enforce { fps: u32 },
+    // Registry and file based games, backup is what revert writes back
+    Applied { fps: u32, backup: StartupBackup, game_path: String },
+    Watch
+}
+
+struct Session {
+    game: &'static Game,
+    kind: SessionKind,
+    cancel: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+    events: EventSink,
+    last_event: Arc<Mutex<Option<Event>>>
+}
+
+impl Session {
+    // Events go to the client that started the session and are kept for status
+    fn new(game: &'static Game, kind: SessionKind, client: &Arc<Client>) -> Session {
+        let last_event: Arc<Mutex<Option<Event>>> = Arc::new(Mutex::new(None));
+        let (client, last, game_id): (Weak<Client>, _, _) = (Arc::downgrade(client), last_event.clone(), game.id);
+        let events = EventSink::new(move |event| {
+            *last.lock().unwrap() = Some(event.clone());
+            let Some(client) = client.upgrade() else { return };
+            let mut params = serde_json::to_value(event).unwrap_or_default();
+            params["game_id"] = json!(game_id);
+            client.send(&json!({ "jsonrpc": "2.0", "method": "event", "params": params }));
+        });
+        Session { game, kind, cancel: Arc::new(AtomicBool::new(false)), thread: None, events, last_event }
+    }
+
+    fn is_running(&self) -> bool { self.thread.as_ref().is_some_and(|t| !t.is_finished()) }
+
+    fn wait_options(&self) -> WaitOptions { WaitOptions { cancel: Some(self.cancel.clone()), ..Default::default() } }
+
+    fn status(&self) -> Value {
+        let (kind, fps) = match &self.kind {
+            SessionKind::Enforce { fps } => ("unlock", Some(*fps)),
+            SessionKind::Applied { fps, .. } => ("unlock", Some(*fps)),
+            SessionKind::Watch => ("watch", None)
+        };
+        json!({ "game_id": self.game.id, "kind": kind, "fps": fps, "running": self.is_running(), "last_event": *self.last_event.lock().unwrap() })
+    }
+}
+
+#[derive(Default)]
+struct Daemon {
+    unlocks: Mutex<HashMap<&'static str, Session>>,
+    watchers: Mutex<HashMap<&'static str, Session>>
+}
+
+impl Daemon {
+    fn serve(&self, client: Arc<Client>) {
+        for line in BufReader::new(ClientReader(&client)).lines() {
+            let Ok(line) = line else { break };
+            if line.trim().is_empty() { continue; }
+            let request: Request = match serde_json::from_str(&line) {
+                Ok(request) => request,
+                Err(e) => { client.send(&error_response(Value::Null, PARSE_ERROR, e.to_string())); continue; }
+            };
+            let response = match self.dispatch(&request.method, request.params, &client) {
+                Some(Ok(result)) => json!({ "jsonrpc": "2.0", "id": request.id, "result": result }),
+                Some(Err(e)) => error_response(request.id.clone().unwrap_or_default(), if e.kind() == ErrorKind::InvalidInput { INVALID_PARAMS } else { SERVER_ERROR }, e.to_string()),
+                None => error_response(request.id.clone().unwrap_or_default(), METHOD_NOT_FOUND, format!("Unknown method {}!", request.method))
+            };
+            if request.id.is_some() { client.send(&response); }
+        }
+    }
+
+    fn dispatch(&self, method: &str, params: Value, client: &Arc<Client>) -> Option<std::io::Result<Value>> {
+        Some(match method {
+            "list_games" => Ok(json!(GAMES.iter().map(|g| json!({ "id": g.id, "name": g.name })).collect::<Vec<_>>())),
+            "status" => Ok(self.status()),
+            "apply" => parse_params(params).and_then(|p| self.apply(p, client)),
+            "revert" => parse_params(params).and_then(|p: GameParams| lookup_game(&p.game_id)).and_then(|g| self.revert(g)),
+            "watch_process" => parse_params(params).and_then(|p: GameParams| lookup_game(&p.game_id)).and_then(|g| self.watch(g, client)),
+            _ => return None
+        })
+    }
+
+    fn status(&self) -> Value {
+        let unlocks = self.unlocks.lock().unwrap();
+        let watchers = self.watchers.lock().unwrap();
+        json!({ "sessions": unlocks.values().chain(watchers.values()).map(Session::status).collect::<Vec<_>>() })
+    }
+
+    fn apply(&self, params: ApplyParams, client: &Arc<Client>) -> std::io::Result<Value> {
+        let refresh = params.refresh.as_deref().map(parse_duration).transpose()?;
+        let request = RunRequest {
+            game_id: params.game_id, fps: params.fps, refresh, path: params.path, disable_vsync: params.disable_vsync, keep_fps_unfocused: params.keep_fps_unfocused, fov: params.fov,
+            unfocused_fps: params.unfocused_fps, schedule: params.schedule, battery_fps: params.battery_fps, battery_thresholds: params.battery_thresholds
+        };
+        // Unknown game or no FPS is a bad request here
+        let opts = resolve_run_options(request).map_err(|e| if e.kind() == ErrorKind::NotFound { Error::new(ErrorKind::InvalidInput, e.to_string()) } else { e })?;
+        let game = opts.game;
+        let mut unlocks = self.unlocks.lock().unwrap();
+        if unlocks.get(game.id).is_some_and(Session::is_running) { return Err(Error::new(ErrorKind::AlreadyExists, format!("{} is already being unlocked, revert it first!", game.name))); }
+
+        let session = match game.unlocker {
+            Unlocker::Hk4e => {
+                let mut session = Session::new(game, SessionKind::Enforce { fps: opts.target_fps }, client);
+                let wait = WaitOptions { timeout: params.timeout.map(Duration::from_secs), pid: params.pid, parent_pid: params.parent_pid, ..session.wait_options() };
+                let options = opts.hk4e(wait, session.events.clone());
+                session.thread = Some(std::thread::spawn(move || {
+                    // Errors and reverts while still waiting are emitted by apply_hk4e itself
+                    if let Err(e) = apply_hk4e(game, &options) && !options.wait.is_cancelled() { eprintln!("{}", e); }
+                }));
+                session
+            }
+            _ => {
+                // Applying again must not back up our own values over what the game had before the first apply
+                let previous = match unlocks.get(game.id).map(|s| &s.kind) { Some(SessionKind::Applied { backup, .. }) => Some(backup.clone()), _ => None };
+                // Registry and file I/O must not hold up status and the other games
+                drop(unlocks);
+                // Refuse what could not be reverted later, this backup also rolls back a write that stopped halfway
+                let current = backup_startup(game, &opts.game_path)?;
+                let fps = opts.startup_fps();
+                let backup = previous.unwrap_or_else(|| current.clone());
+                let session = Session::new(game, SessionKind::Applied { fps, backup, game_path: opts.game_path.clone() }, client);
+                if let Err(e) = apply_startup(game, fps, &opts.game_path, &session.events) {
+                    if let Err(restore) = restore_startup(game, &opts.game_path, &current) { eprintln!("Failed to roll back {} settings: {}!", game.name, restore); }
+                    return Err(e);
+                }
+                unlocks = self.unlocks.lock().unwrap();
+                session
+            }
+        };
+        let result = session.status();
+        unlocks.insert(game.id, session);
+        Ok(result)
+    }
+
+    fn revert(&self, game: &'static Game) -> std::io::Result<Value> {
+        let Some(mut session) = self.unlocks.lock().unwrap().remove(game.id) else { return Err(Error::new(ErrorKind::NotFound, format!("{} has not been unlocked!", game.name))); };
+        // The enforcement loop restores the original values itself before returning
+        session.cancel.store(true, Ordering::SeqCst);
+        if let Some(thread) = session.thread.take() { let _ = thread.join(); }
+        if let SessionKind::Applied { backup, game_path, .. } = &session.kind {
+            if let Err(e) = restore_startup(game, game_path, backup) {
+                session.events.emit(Event::Error { message: e.to_string() });
+                return Err(e);
+            }
+            session.events.emit(Event::Reverted);
+        }
+        // Genshin only reports reverted once original values went back, not when it was still being waited for
+        let reverted = matches!(*session.last_event.lock().unwrap(), Some(Event::Reverted));
+        Ok(json!({ "game_id": game.id, "reverted": reverted }))
+    }
+
+    fn watch(&self, game: &'static Game, client: &Arc<Client>) -> std::io::Result<Value> {
+        let mut watchers = self.watchers.lock().unwrap();
+        if let Some(session) = watchers.get(game.id) && session.is_running() { return Ok(session.status()); }
+        let mut session = Session::new(game, SessionKind::Watch, client);
+        let (wait, events) = (session.wait_options(), session.events.clone());
+        session.thread = Some(std::thread::spawn(move || {
+            let target = game.executable_name();
+            events.emit(Event::WaitingForProcess { executable: target.to_string() });
+            match wait_for_process(target, &wait) {
+                Ok(handle) => {
+                    let pid = get_pid_from_handle(handle);
+                    events.emit(Event::ProcessFound { pid });
+                    if wait_for_exit_or_cancel(handle, Duration::MAX, &wait) { events.emit(Event::ProcessExited { pid }); }
+                    unsafe { CloseHandle(handle); }
+                }
+                Err(_) if wait.is_cancelled() => {}
+                Err(e) => events.emit(Event::Error { message: e.to_string() })
+            }
+        }));
+        let result = session.status();
+        watchers.insert(game.id, session);
+        Ok(result)
+    }
+}
+
+// Registry and file based games, the value is picked up on the next start
+fn apply_startup(game: &Game, fps: u32, game_path: &str, events: &EventSink) -> std::io::Result<()> {
+    match game.unlocker {
+        Unlocker::Hkrpg => apply_hkrpg(game, fps, events),
+        Unlocker::Bh3 => apply_bh3(game, fps, events),
+        Unlocker::Wuwa => apply_wuwa(game, fps, game_path, events),
+        Unlocker::Hk4e => Ok(())
+    }
+}
+
+fn parse_params<T: for<'de> Deserialize<'de>>(params: Value) -> std::io::Result<T> {
+    serde_json::from_value(params).map_err(|e| Error::new(ErrorKind::InvalidInput, format!("Invalid params: {}", e)))
+}
+
+fn lookup_game(game_id: &str) -> std::io::Result<&'static Game> {
+    find_game(game_id).ok_or_else(|| Error::new(ErrorKind::InvalidInput, format!("GameID {} not recognized!", game_id)))
+}
+
+fn error_response(id: Value, code: i64, message: String) -> Value {
+    json!({ "jsonrpc": "2.0", "id": id, "error": { "code": code, "message": message } })
+}
@@ -0,0 +1,159 @@
+use std::fmt;
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::str::FromStr;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use windows::core::PCWSTR;
+use windows::Win32::Graphics::Gdi::{EnumDisplaySettingsW, DEVMODEW, ENUM_CURRENT_SETTINGS};
+use crate::wine_helpers::{is_wine, unix_path};
+
+// Either a plain number or "monitor", optionally adjusted as "monitor-1", "monitor+10" or "monitor/2"
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FpsTarget {
+    Fixed(u32),
+    Monitor { offset: i32, divisor: u32 }
+}
+
+impl FpsTarget {
+    pub fn resolve(&self) -> std::io::Result<u32> {
+        match *self {
+            FpsTarget::Fixed(fps) => Ok(fps),
+            FpsTarget::Monitor { offset, divisor } => {
+                let rate = monitor_refresh_rate().ok_or_else(|| Error::new(ErrorKind::NotFound, "Could not read the monitor refresh rate, pass a number instead!"))?;
+                let fps = (rate / divisor).saturating_add_signed(offset).max(1);
+                eprintln!("Monitor refresh rate is {} Hz, targeting {} FPS", rate, fps);
+                Ok(fps)
+            }
+        }
+    }
+}
+
+impl FromStr for FpsTarget {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || Error::new(ErrorKind::InvalidInput, format!("Invalid FPS \"{}\", expected a number, monitor, monitor-N, monitor+N or monitor/N!", s));
+        let s = s.trim();
+        let Some(rest) = s.strip_prefix("monitor") else { return s.parse().map(FpsTarget::Fixed).map_err(|_| invalid()) };
+        let rest = rest.trim();
+        let (offset, divisor) = if rest.is_empty() { (0, 1) } else {
+            let (op, value) = rest.split_at(rest.chars().next().map_or(0, char::len_utf8));
+            let value = value.trim();
+            match op {
+                "-" => (-value.parse::<i32>().map_err(|_| invalid())?, 1),
+                "+" => (value.parse::<i32>().map_err(|_| invalid())?, 1),
+                "/" => (0, value.parse::<u32>().ok().filter(|d| *d > 0).ok_or_else(invalid)?),
+                _ => return Err(invalid())
+            }
+        };
+        Ok(FpsTarget::Monitor { offset, divisor })
+    }
+}
+
+impl fmt::Display for FpsTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            FpsTarget::Fixed(fps) => write!(f, "{}", fps),
+            FpsTarget::Monitor { offset: 0, divisor: 1 } => write!(f, "monitor"),
+            FpsTarget::Monitor { offset, divisor: 1 } if offset < 0 => write!(f, "monitor-{}", -offset),
+            FpsTarget::Monitor { offset, divisor: 1 } => write!(f, "monitor+{}", offset),
+            FpsTarget::Monitor { divisor, .. } => write!(f, "monitor/{}", divisor)
+        }
+    }
+}
+
+// Numbers stay numbers in config.json so older profiles keep loading
+impl Serialize for FpsTarget {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            FpsTarget::Fixed(fps) => serializer.serialize_u32(*fps),
+            target => serializer.serialize_str(&target.to_string())
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for FpsTarget {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Raw { Number(u32), Text(String) }
+        match Raw::deserialize(deserializer)? {
+            Raw::Number(fps) => Ok(FpsTarget::Fixed(fps)),
+            Raw::Text(s) => s.parse().map_err(serde::de::Error::custom)
+        }
+    }
+}
+
+// Current mode of the primary display, under Wine falls back to xrandr and then to the EDID of connected DRM outputs
+pub fn monitor_refresh_rate() -> Option<u32> {
+    let mut mode = DEVMODEW { dmSize: size_of::<DEVMODEW>() as u16, ..Default::default() };
+    let ok = unsafe { EnumDisplaySettingsW(PCWSTR(std::ptr::null()), ENUM_CURRENT_SETTINGS, &mut mode) }.as_bool();
+    // 0 and 1 mean "hardware default"
+    if ok && mode.dmDisplayFrequency > 1 { return Some(mode.dmDisplayFrequency); }
+    if !is_wine() { return None; }
+    xrandr_output().and_then(|output| xrandr_refresh_rate(&output)).or_else(|| drm_refresh_rate(&unix_path("/sys/class/drm")))
+}
+
+fn xrandr_output() -> Option<String> {
+    let output = Command::new(unix_path("/usr/bin/xrandr")).arg("--current").output().ok()?;
+    output.status.success().then(|| String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+// Active mode (marked "*") of the primary output, or of the first connected output without a primary
+fn xrandr_refresh_rate(output: &str) -> Option<u32> {
+    let mut rates = Vec::new();
+    let (mut connected, mut primary) = (false, false);
+    for line in output.lines() {
+        if !line.starts_with(' ') { connected = line.contains(" connected"); primary = line.contains(" connected primary"); continue; }
+        if !connected { continue; }
+        let active = line.split_whitespace().skip(1).find(|t| t.contains('*')).and_then(|t| t.trim_end_matches(['*', '+']).parse::<f64>().ok());
+        if let Some(rate) = active { rates.push((primary, rate.round() as u32)); }
+    }
+    rates.iter().find(|(primary, _)| *primary).or(rates.first()).map(|(_, rate)| *rate)
+}
+
+// The kernel does not say which mode is active, so this is the preferred mode of the internal panel or else the first connected output
+fn drm_refresh_rate(root: &Path) -> Option<u32> {
+    let mut outputs: Vec<PathBuf> = std::fs::read_dir(root).ok()?.flatten().map(|e| e.path())
+        .filter(|dir| std::fs::read_to_string(dir.join("status")).is_ok_and(|s| s.trim() == "connected"))
+        .collect();
+    outputs.sort_by_key(|dir| (!dir.file_name().is_some_and(|n| n.to_string_lossy().contains("-eDP-")), dir.clone()));
+    outputs.iter().find_map(|dir| std::fs::read(dir.join("edid")).ok().and_then(|edid| edid_refresh_rate(&edid)))
+}
+
+// First detailed timing descriptor of the base block is the preferred mode
+fn edid_refresh_rate(edid: &[u8]) -> Option<u32> {
+    let dtd = edid.get(54..72)?;
+    let pixel_clock = u16::from_le_bytes([dtd[0], dtd[1]]) as u64 * 10_000;
+    if pixel_clock == 0 { return None; }
+    let h_total = (dtd[2] as u64 | ((dtd[4] as u64 & 0xF0) << 4)) + (dtd[3] as u64 | ((dtd[4] as u64 & 0x0F) << 8));
+    let v_total = (dtd[5] as u64 | ((dtd[7] as u64 & 0xF0) << 4)) + (dtd[6] as u64 | ((dtd[7] as u64 & 0x0F) << 8));
+    if h_total == 0 || v_total == 0 { return None; }
+    Some(((pixel_clock as f64) / (h_total * v_total) as f64).round() as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const XRANDR: &str = "Screen 0: minimum 320 x 200, current 4480 x 1440, maximum 16384 x 16384
+HDMI-1 connected 2560x1440+1920+0 (normal left inverted right x axis y axis) 597mm x 336mm
+   2560x1440    143.97*+ 120.00    59.95
+   1920x1080     60.00    50.00
+eDP-1 connected primary 1920x1080+0+0 (normal left inverted right x axis y axis) 344mm x 193mm
+   1920x1080     60.01 +  59.97*   59.96
+DP-1 disconnected (normal left inverted right x axis y axis)
+";
+
+    #[test]
+    fn xrandr_prefers_active_mode_of_primary() {
+        assert_eq!(xrandr_refresh_rate(XRANDR), Some(60));
+    }
+
+    #[test]
+    fn xrandr_without_primary_takes_first_connected() {
+        assert_eq!(xrandr_refresh_rate(&XRANDR.replace(" primary", "")), Some(144));
+        assert_eq!(xrandr_refresh_rate("Screen 0: minimum 320 x 200\nDP-1 disconnected (normal)\n"), None);
+    }
+}
@@ -0,0 +1,75 @@
+use std::fmt;
+use std::fs::File;
+use std::io::Write;
+use std::os::windows::io::{FromRawHandle, RawHandle};
+use std::sync::{Arc, Mutex};
+use serde::Serialize;
+use windows::Win32::System::Console::{GetStdHandle, SetStdHandle, STD_ERROR_HANDLE, STD_OUTPUT_HANDLE};
+use crate::signature_helpers::{PatchTarget, PatchValue};
+
+// Progress of an unlock as seen by whoever drives it, serialized as {"event": "process_found", "pid": 1234}
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    WaitingForProcess { executable: String },
+    ProcessFound { pid: u32 },
+    ModuleBase { module: String, base: usize, size: usize },
+    SignatureMatched { name: String, target: PatchTarget, module: String, location: String },
+    Patched { fps: u32 },
+    // The game wrote its own value over ours, it gets patched again right after
+    RevertedByGame { target: PatchTarget, value: PatchValue },
+    // Original values were written back on request
+    Reverted,
+    ProcessExited { pid: u32 },
+    Error { message: String }
+}
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventFormat {
+    // One JSON object per line on stdout, the usual messages move to stderr
+    Ndjson
+}
+
+impl EventFormat {
+    pub fn sink(self) -> EventSink {
+        match self {
+            EventFormat::Ndjson => {
+                let out = Mutex::new(take_stdout());
+                EventSink::new(move |event| {
+                    let Ok(line) = serde_json::to_string(event) else { return };
+                    let Ok(mut out) = out.lock() else { return };
+                    let _ = writeln!(out, "{}", line);
+                    let _ = out.flush();
+                })
+            }
+        }
+    }
+}
+
+// Points the process stdout at stderr and returns a writer for the original one, so println! output cannot end up between events
+fn take_stdout() -> Box<dyn Write + Send> {
+    let _ = std::io::stdout().flush();
+    unsafe {
+        let (Ok(stdout), Ok(stderr)) = (GetStdHandle(STD_OUTPUT_HANDLE), GetStdHandle(STD_ERROR_HANDLE)) else { return Box::new(std::io::stdout()) };
+        if !SetStdHandle(STD_OUTPUT_HANDLE, stderr).as_bool() { return Box::new(std::io::stdout()); }
+        // Leaked so the handle is never closed, it still belongs to the process
+        let file: &'static File = Box::leak(Box::new(File::from_raw_handle(stdout.0 as RawHandle)));
+        Box::new(file)
+    }
+}
+
+type Handler = Arc<dyn Fn(&Event) + Send + Sync>;
+
+// Where events go, nowhere by default
+#[derive(Clone, Default)]
+pub struct EventSink(Option<Handler>);
+
+impl EventSink {
+    pub fn new(sink: impl Fn(&Event) + Send + Sync + 'static) -> EventSink { EventSink(Some(Arc::new(sink))) }
+
+    pub fn emit(&self, event: Event) { if let Some(sink) = &self.0 { sink(&event); } }
+}
+
+impl fmt::Debug for EventSink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result { f.write_str(if self.0.is_some() { "EventSink" } else { "EventSink(none)" }) }
+}
@@ -0,0 +1,203 @@
+use std::path::Path;
+use configparser::ini::Ini;
+use crate::pe_helpers::file_version;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Unlocker {
+    Hk4e,
+    Hkrpg,
+    Bh3,
+    Wuwa
+}
+
+impl Unlocker {
+    // Registry value name prefix and the JSON field holding the FPS cap
+    pub fn fps_setting(&self) -> Option<(&'static str, &'static str)> {
+        match self {
+            Unlocker::Hkrpg => Some(("GraphicsSettings_Model", "FPS")),
+            Unlocker::Bh3 => Some(("PersonalGraphicsSettingV2", "TargetFrameRateForInLevel")),
+            _ => None
+        }
+    }
+
+    // Highest value worth writing, used to clamp targets derived from the monitor
+    pub fn max_fps(&self) -> u32 {
+        match self {
+            Unlocker::Hk4e => 1000,
+            Unlocker::Hkrpg | Unlocker::Wuwa => 120,
+            // apply_bh3 treats 300 and above as a request for its 60 FPS fallback
+            Unlocker::Bh3 => 299
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Game {
+    pub id: &'static str,
+    pub name: &'static str,
+    pub unlocker: Unlocker,
+    // Unity PlayerPrefs root under HKEY_CURRENT_USER, None for games that keep settings on disk
+    pub registry_key: Option<&'static str>,
+    // Relative to the game install directory
+    pub executable: &'static str,
+    pub data_dir: &'static str
+}
+
+impl Game {
+    pub fn executable_name(&self) -> &'static str { self.executable.rsplit('/').next().unwrap_or(self.executable) }
+}
+
+pub const GAMES: &[Game] = &[
+    Game { id: "hk4e_global", name: "GenshinImpact", unlocker: Unlocker::Hk4e, registry_key: Some("Software\\miHoYo\\Genshin Impact"), executable: "GenshinImpact.exe", data_dir: "GenshinImpact_Data" },
+    Game { id: "hk4e_cn", name: "GenshinImpact (CN)", unlocker: Unlocker::Hk4e, registry_key: Some("Software\\miHoYo\\原神"), executable: "YuanShen.exe", data_dir: "YuanShen_Data" },
+    Game { id: "hkrpg_global", name: "Honkai: StarRail", unlocker: Unlocker::Hkrpg, registry_key: Some("Software\\Cognosphere\\Star Rail"), executable: "StarRail.exe", data_dir: "StarRail_Data" },
+    Game { id: "hkrpg_cn", name: "Honkai: StarRail (CN)", unlocker: Unlocker::Hkrpg, registry_key: Some("Software\\miHoYo\\崩坏：星穹铁道"), executable: "StarRail.exe", data_dir: "StarRail_Data" },
+    Game { id: "bh3_global", name: "HonkaiImpact 3rd", unlocker: Unlocker::Bh3, registry_key: Some("Software\\miHoYo\\Honkai Impact 3rd"), executable: "BH3.exe", data_dir: "BH3_Data" },
+    Game { id: "bh3_sea", name: "HonkaiImpact 3rd (SEA)", unlocker: Unlocker::Bh3, registry_key: Some("Software\\miHoYo\\Honkai Impact 3"), executable: "BH3.exe", data_dir: "BH3_Data" },
+    Game { id: "bh3_jp", name: "HonkaiImpact 3rd (JP)", unlocker: Unlocker::Bh3, registry_key: Some("Software\\miHoYo\\崩壊3rd"), executable: "BH3.exe", data_dir: "BH3_Data" },
+    Game { id: "bh3_kr", name: "HonkaiImpact 3rd (KR)", unlocker: Unlocker::Bh3, registry_key: Some("Software\\miHoYo\\붕괴3rd"), executable: "BH3.exe", data_dir: "BH3_Data" },
+    Game { id: "bh3_tw", name: "HonkaiImpact 3rd (TW)", unlocker: Unlocker::Bh3, registry_key: Some("Software\\miHoYo\\崩壞3rd"), executable: "BH3.exe", data_dir: "BH3_Data" },
+    Game { id: "bh3_cn", name: "HonkaiImpact 3rd (CN)", unlocker: Unlocker::Bh3, registry_key: Some("Software\\miHoYo\\崩坏3"), executable: "BH3.exe", data_dir: "BH3_Data" },
+    Game { id: "wuwa_global", name: "WutheringWaves", unlocker: Unlocker::Wuwa, registry_key: None, executable: "Client/Binaries/Win64/Client-Win64-Shipping.exe", data_dir: "Client/Saved" },
+    Game { id: "wuwa_cn", name: "WutheringWaves (CN)", unlocker: Unlocker::Wuwa, registry_key: None, executable: "Client/Binaries/Win64/Client-Win64-Shipping.exe", data_dir: "Client/Saved" },
+];
+
+pub fn find_game(game_id: &str) -> Option<&'static Game> { GAMES.iter().find(|g| g.id.eq_ignore_ascii_case(game_id)) }
+
+// Regional builds share their executable, so when several games match the region is told apart by, in order:
+// the Unity company/product pair in app.info (it names the registry key, which differs per region), the mainland
+// only SDK directory for WutheringWaves and the launcher config.ini. Without any of these the global entry wins
+pub fn detect_game(game_path: &Path) -> Option<&'static Game> {
+    let candidates: Vec<&'static Game> = GAMES.iter().filter(|g| game_path.join(g.executable).is_file()).collect();
+    if candidates.len() <= 1 { return candidates.first().copied(); }
+
+    // Unity writes PlayerPrefs to Software\<company>\<product>, both of which are stored in app.info
+    if let Ok(info) = std::fs::read_to_string(game_path.join(candidates[0].data_dir).join("app.info")) {
+        let mut lines = info.lines().map(|l| l.trim());
+        if let (Some(company), Some(product)) = (lines.next(), lines.next()) {
+            let key = format!("Software\\{}\\{}", company, product);
+            if let Some(g) = candidates.iter().find(|g| g.registry_key == Some(key.as_str())) { return Some(g); }
+        }
+    }
+
+    if candidates[0].unlocker == Unlocker::Wuwa {
+        let mainland = game_path.join("Client/Binaries/Win64/ThirdParty/KrPcSdk_Mainland").is_dir();
+        return candidates.iter().find(|g| g.id.ends_with(if mainland { "_cn" } else { "_global" })).copied();
+    }
+
+    let mut ini = Ini::new();
+    if ini.load(game_path.join("config.ini").to_string_lossy().as_ref()).is_ok() {
+        let cps = ini.get("General", "cps").unwrap_or_default().to_lowercase();
+        let channel = ini.get("General", "channel").unwrap_or_default();
+        let sub_channel = ini.get("General", "sub_channel").unwrap_or_default();
+        // Global builds ship with the hoyoverse cps, everything on channel 14 (bilibili) or other cps values is mainland
+        let cn = (!cps.is_empty() && !cps.contains("hoyoverse")) || channel == "14" || sub_channel == "14";
+        if let Some(g) = candidates.iter().find(|g| g.id.ends_with(if cn { "_cn" } else { "_global" })) { return Some(g); }
+    }
+    candidates.first().copied()
+}
+
+// Returns the version and where it was read from: launcher config.ini, the game's persistent version files or the executable version resource
+pub fn detect_game_version(game: &Game, game_path: &Path) -> Option<(String, &'static str)> {
+    let mut ini = Ini::new();
+    if ini.load(game_path.join("config.ini").to_string_lossy().as_ref()).is_ok() && let Some(v) = ini.get("General", "game_version").filter(|v| !v.is_empty()) {
+        return Some((v, "config.ini"));
+    }
+    for file in ["ScriptVersion", "res_versions_persist"] {
+        let Ok(content) = std::fs::read(game_path.join(game.data_dir).join("Persistent").join(file)) else { continue };
+        if let Some(v) = find_version_string(&String::from_utf8_lossy(&content)) { return Some((v, "persistent data")); }
+    }
+    let exe = std::fs::read(game_path.join(game.executable)).ok()?;
+    file_version(&exe).map(|v| (v, "executable version resource"))
+}
+
+// First "x.y.z" looking token, version files carry build numbers and hashes around it
+fn find_version_string(content: &str) -> Option<String> {
+    content.split(|c: char| !(c.is_ascii_digit() || c == '.')).find(|t| {
+        let parts: Vec<&str> = t.split('.').collect();
+        parts.len() >= 3 && parts.iter().all(|p| !p.is_empty())
+    }).map(|t| t.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    // Fake install directory, removed again when dropped
+    struct Install(PathBuf);
+
+    impl Install {
+        fn new(name: &str) -> Install {
+            let dir = std::env::temp_dir().join(format!("keqing_unlock_detect_{}_{}", std::process::id(), name));
+            let _ = std::fs::remove_dir_all(&dir);
+            std::fs::create_dir_all(&dir).unwrap();
+            Install(dir)
+        }
+
+        fn file(self, path: &str, content: &str) -> Install {
+            let path = self.0.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, content).unwrap();
+            self
+        }
+
+        fn dir(self, path: &str) -> Install {
+            std::fs::create_dir_all(self.0.join(path)).unwrap();
+            self
+        }
+    }
+
+    impl Drop for Install {
+        fn drop(&mut self) { let _ = std::fs::remove_dir_all(&self.0); }
+    }
+
+    const WUWA_EXE: &str = "Client/Binaries/Win64/Client-Win64-Shipping.exe";
+
+    fn bh3(name: &str, product: &str) -> Install { Install::new(name).file("BH3.exe", "").file("BH3_Data/app.info", &format!("miHoYo\n{}\n", product)) }
+
+    fn starrail(name: &str, config: &str) -> Install { Install::new(name).file("StarRail.exe", "").file("config.ini", config) }
+
+    #[test]
+    fn detects_every_game() {
+        let cases = [
+            ("hk4e_global", Install::new("hk4e_global").file("GenshinImpact.exe", "")),
+            ("hk4e_cn", Install::new("hk4e_cn").file("YuanShen.exe", "")),
+            ("hkrpg_global", starrail("hkrpg_global", "[General]\ncps=hoyoverse_PC\nchannel=1\n")),
+            ("hkrpg_cn", starrail("hkrpg_cn", "[General]\ncps=gw_PC\nchannel=1\n")),
+            ("bh3_global", bh3("bh3_global", "Honkai Impact 3rd")),
+            ("bh3_sea", bh3("bh3_sea", "Honkai Impact 3")),
+            ("bh3_jp", bh3("bh3_jp", "崩壊3rd")),
+            ("bh3_kr", bh3("bh3_kr", "붕괴3rd")),
+            ("bh3_tw", bh3("bh3_tw", "崩壞3rd")),
+            ("bh3_cn", bh3("bh3_cn", "崩坏3")),
+            ("wuwa_global", Install::new("wuwa_global").file(WUWA_EXE, "")),
+            ("wuwa_cn", Install::new("wuwa_cn").file(WUWA_EXE, "").dir("Client/Binaries/Win64/ThirdParty/KrPcSdk_Mainland"))
+        ];
+        for (id, install) in &cases { assert_eq!(detect_game(&install.0).map(|g| g.id), Some(*id), "{}", install.0.display()); }
+        for game in GAMES { assert!(cases.iter().any(|(id, _)| *id == game.id), "{} has no detection case", game.id); }
+    }
+
+    #[test]
+    fn bilibili_channel_is_mainland() {
+        let install = starrail("hkrpg_bilibili", "[General]\ncps=bilibili_PC\nchannel=14\nsub_channel=0\n");
+        assert_eq!(detect_game(&install.0).map(|g| g.id), Some("hkrpg_cn"));
+        let install = starrail("hkrpg_sub_channel", "[General]\nchannel=1\nsub_channel=14\n");
+        assert_eq!(detect_game(&install.0).map(|g| g.id), Some("hkrpg_cn"));
+    }
+
+    #[test]
+    fn shared_executable_without_hints_is_global() {
+        let install = Install::new("hkrpg_bare").file("StarRail.exe", "");
+        assert_eq!(detect_game(&install.0).map(|g| g.id), Some("hkrpg_global"));
+        // Unknown app.info product falls through to the first candidate as well
+        let install = bh3("bh3_unknown", "Some Other Product");
+        assert_eq!(detect_game(&install.0).map(|g| g.id), Some("bh3_global"));
+    }
+
+    #[test]
+    fn nothing_installed() {
+        let install = Install::new("empty");
+        assert!(detect_game(&install.0).is_none());
+    }
+}
@@ -1,66 +1,175 @@
 use std::ffi::{OsString};
 use std::os::windows::ffi::OsStringExt;
-use std::time::Duration;
-use windows::Win32::Foundation::{CloseHandle, HANDLE, INVALID_HANDLE_VALUE, STILL_ACTIVE};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, Once, Weak};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use windows::Win32::Foundation::{CloseHandle, BOOL, E_ACCESSDENIED, HANDLE, INVALID_HANDLE_VALUE, WAIT_TIMEOUT};
+use windows::Win32::System::Console::{SetConsoleCtrlHandler, CTRL_BREAK_EVENT, CTRL_C_EVENT};
 use windows::Win32::System::Diagnostics::Debug::{ReadProcessMemory, WriteProcessMemory};
 use windows::Win32::System::Diagnostics::ToolHelp::{CreateToolhelp32Snapshot, Module32FirstW, Module32NextW, Process32FirstW, Process32NextW, MODULEENTRY32W, PROCESSENTRY32W, TH32CS_SNAPMODULE, TH32CS_SNAPPROCESS};
-use windows::Win32::System::Threading::{GetExitCodeProcess, GetProcessId, OpenProcess, PROCESS_ALL_ACCESS};
+use windows::core::PWSTR;
+use windows::Win32::System::Threading::{GetProcessId, OpenProcess, QueryFullProcessImageNameW, PROCESS_ACCESS_RIGHTS, PROCESS_NAME_WIN32, PROCESS_QUERY_LIMITED_INFORMATION, PROCESS_SYNCHRONIZE, PROCESS_VM_OPERATION, PROCESS_VM_READ, PROCESS_VM_WRITE, WaitForSingleObject};
+use crate::wine_helpers::ptrace_scope_hint;
+
+#[derive(Debug, Clone, Copy)]
+pub struct ProcessInfo {
+    pub pid: u32,
+    pub parent_pid: u32
+}
 
-pub fn wait_for_handle_by_name(target: &str) -> HANDLE {
-    loop {
-        unsafe {
-            let snapshot = match CreateToolhelp32Snapshot(TH32CS_SNAPPROCESS, 0) {
-                Ok(h) => h,
-                Err(_) => { std::thread::sleep(Duration::from_millis(100)); continue; }
-            };
-
-            let mut entry = PROCESSENTRY32W {
-                dwSize: size_of::<PROCESSENTRY32W>() as u32,
-                ..Default::default()
-            };
-
-            if !Process32FirstW(snapshot, &mut entry).as_bool() {
-                CloseHandle(snapshot).unwrap();
-                std::thread::sleep(Duration::from_millis(100));
-                continue;
-            }
+pub fn find_processes_by_name(target: &str) -> Vec<ProcessInfo> {
+    let mut found = Vec::new();
+    unsafe {
+        let Ok(snapshot) = CreateToolhelp32Snapshot(TH32CS_SNAPPROCESS, 0) else { return found };
+        let mut entry = PROCESSENTRY32W {
+            dwSize: size_of::<PROCESSENTRY32W>() as u32,
+            ..Default::default()
+        };
 
-            let handle = loop {
+        if Process32FirstW(snapshot, &mut entry).as_bool() {
+            loop {
                 let len = entry.szExeFile.iter().position(|&c| c == 0).unwrap_or(entry.szExeFile.len());
                 let exe_name = OsString::from_wide(&entry.szExeFile[..len]).to_string_lossy().to_string();
-                if exe_name.eq_ignore_ascii_case(target) {
-                    let proc_handle = OpenProcess(PROCESS_ALL_ACCESS, false, entry.th32ProcessID);
-                    break proc_handle;
-                }
-                if !Process32NextW(snapshot, &mut entry).as_bool() { break Ok(HANDLE::default()); }
-            };
+                if exe_name.eq_ignore_ascii_case(target) { found.push(ProcessInfo { pid: entry.th32ProcessID, parent_pid: entry.th32ParentProcessID }); }
+                if !Process32NextW(snapshot, &mut entry).as_bool() { break; }
+            }
+        }
+        CloseHandle(snapshot);
+    }
+    found
+}
 
-            CloseHandle(snapshot).unwrap();
-            let handle = handle.unwrap();
-            if !handle.is_invalid() { return handle; }
-            std::thread::sleep(Duration::from_millis(100));
+#[derive(Debug, Clone)]
+pub struct WaitOptions {
+    // Give up after this long, waits forever when unset
+    pub timeout: Option<Duration>,
+    // Only this exact process
+    pub pid: Option<u32>,
+    // Only processes started by this one, e.g. the launcher
+    pub parent_pid: Option<u32>,
+    pub poll_interval: Duration,
+    // Set from another thread to stop waiting, and enforcing once attached
+    pub cancel: Option<Arc<AtomicBool>>
+}
+
+impl Default for WaitOptions {
+    fn default() -> Self { WaitOptions { timeout: None, pid: None, parent_pid: None, poll_interval: Duration::from_millis(100), cancel: None } }
+}
+
+impl WaitOptions {
+    pub fn is_cancelled(&self) -> bool { self.cancel.as_ref().is_some_and(|c| c.load(Ordering::SeqCst)) }
+}
+
+// Enough to read/write memory, query the image path and wait on exit. Anti-cheat tends to strip anything broader
+const PROCESS_ACCESS: PROCESS_ACCESS_RIGHTS = PROCESS_ACCESS_RIGHTS(PROCESS_VM_READ.0 | PROCESS_VM_WRITE.0 | PROCESS_VM_OPERATION.0 | PROCESS_QUERY_LIMITED_INFORMATION.0 | PROCESS_SYNCHRONIZE.0);
+
+// Cancel flags of the calls that asked for Ctrl-C, nobody else is affected by it
+static CTRL_C_TOKENS: Mutex<Vec<Weak<AtomicBool>>> = Mutex::new(Vec::new());
+static CTRL_C_HANDLER: Once = Once::new();
+
+// Ctrl-C sets the returned flag (meant for WaitOptions::cancel) while it is alive. Without a live flag, or once all are set, the default handler terminates as usual
+pub fn cancel_on_ctrl_c() -> Arc<AtomicBool> {
+    let token = Arc::new(AtomicBool::new(false));
+    let mut tokens = CTRL_C_TOKENS.lock().unwrap();
+    tokens.retain(|t| t.strong_count() > 0);
+    tokens.push(Arc::downgrade(&token));
+    CTRL_C_HANDLER.call_once(|| unsafe { SetConsoleCtrlHandler(Some(ctrl_handler), true); });
+    token
+}
+
+unsafe extern "system" fn ctrl_handler(ctrl_type: u32) -> BOOL {
+    if ctrl_type != CTRL_C_EVENT && ctrl_type != CTRL_BREAK_EVENT { return false.into(); }
+    let Ok(tokens) = CTRL_C_TOKENS.lock() else { return false.into() };
+    let cancelled = tokens.iter().filter_map(Weak::upgrade).filter(|t| !t.swap(true, Ordering::SeqCst)).count();
+    if cancelled > 0 { eprintln!("Ctrl-C received, stopping..."); }
+    (cancelled > 0).into()
+}
+
+pub fn wait_for_process(target: &str, options: &WaitOptions) -> std::io::Result<HANDLE> {
+    let started = Instant::now();
+    loop {
+        if options.is_cancelled() { return Err(std::io::Error::new(std::io::ErrorKind::Interrupted, format!("Waiting for {} was cancelled!", target))); }
+        if let Some(timeout) = options.timeout && started.elapsed() >= timeout { return Err(std::io::Error::new(std::io::ErrorKind::TimedOut, format!("{} did not start within {}s!", target, timeout.as_secs()))); }
+        match try_open_process(target, options) {
+            Ok(Some(handle)) => return Ok(handle),
+            Ok(None) => std::thread::sleep(options.poll_interval),
+            Err(e) => return Err(e)
         }
     }
 }
 
-pub fn is_alive(handle: HANDLE) -> bool {
-    unsafe {
-        let mut exitcode: u32 = 0;
-        let ok = GetExitCodeProcess(handle, &mut exitcode as *mut _).as_bool();
-        if !ok { return false; }
-        exitcode == STILL_ACTIVE.0 as u32
+fn try_open_process(target: &str, options: &WaitOptions) -> std::io::Result<Option<HANDLE>> {
+    let candidates: Vec<ProcessInfo> = find_processes_by_name(target).into_iter()
+        .filter(|p| options.pid.is_none_or(|pid| p.pid == pid))
+        .filter(|p| options.parent_pid.is_none_or(|parent| p.parent_pid == parent))
+        .collect();
+    let process = match candidates.as_slice() {
+        [] => return Ok(None),
+        [process] => *process,
+        _ => {
+            let pids: Vec<String> = candidates.iter().map(|p| p.pid.to_string()).collect();
+            return Err(std::io::Error::other(format!("Multiple {} processes found (pids {}), pick one with --pid!", target, pids.join(", "))));
+        }
+    };
+
+    let handle = match unsafe { OpenProcess(PROCESS_ACCESS, false, process.pid) } {
+        Ok(handle) if !handle.is_invalid() => handle,
+        Err(e) if is_access_denied(&e) => return Err(access_denied(format!("Access to {} (pid {}) was denied", target, process.pid))),
+        // Exited between the snapshot and opening it
+        _ => return Ok(None)
+    };
+    // The pid could have been reused in the meantime, make sure it is still the game
+    let same_image = get_process_path(handle).and_then(|p| p.file_name().map(|n| n.to_string_lossy().eq_ignore_ascii_case(target))).unwrap_or(false);
+    if !same_image { unsafe { CloseHandle(handle); } return Ok(None); }
+    Ok(Some(handle))
+}
+
+pub fn is_access_denied(error: &windows::core::Error) -> bool { error.code() == E_ACCESSDENIED }
+
+// PermissionDenied error with a hint on what to change, ptrace restrictions are the usual culprit under Wine
+pub fn access_denied(context: String) -> std::io::Error {
+    let hint = ptrace_scope_hint().unwrap_or("Try running as administrator".to_string());
+    std::io::Error::new(std::io::ErrorKind::PermissionDenied, format!("{}! {}", context, hint))
+}
+
+// Blocks until the process exits or timeout passes, true once it is gone. Exit code 259 is a valid exit, so no STILL_ACTIVE checks
+pub fn wait_for_exit(handle: HANDLE, timeout: Duration) -> bool {
+    let millis = timeout.as_millis().min(u32::MAX as u128 - 1) as u32;
+    unsafe { WaitForSingleObject(handle, millis) != WAIT_TIMEOUT.0 }
+}
+
+// How often a cancellable wait checks its flag
+const CANCEL_POLL: Duration = Duration::from_millis(250);
+
+// wait_for_exit that also returns false early once the options get cancelled
+pub fn wait_for_exit_or_cancel(handle: HANDLE, timeout: Duration, options: &WaitOptions) -> bool {
+    if options.cancel.is_none() { return wait_for_exit(handle, timeout); }
+    let deadline = Instant::now().checked_add(timeout);
+    loop {
+        if options.is_cancelled() { return false; }
+        let left = deadline.map_or(Duration::MAX, |d| d.saturating_duration_since(Instant::now()));
+        if wait_for_exit(handle, left.min(CANCEL_POLL)) { return true; }
+        if left <= CANCEL_POLL { return false; }
     }
 }
 
 pub fn get_pid_from_handle(process_handle: HANDLE) -> u32 { unsafe { GetProcessId(process_handle) } }
 
+pub fn get_process_path(process_handle: HANDLE) -> Option<PathBuf> {
+    let mut buffer = [0u16; 1024];
+    let mut len = buffer.len() as u32;
+    let success = unsafe { QueryFullProcessImageNameW(process_handle, PROCESS_NAME_WIN32, PWSTR(buffer.as_mut_ptr()), &mut len).as_bool() };
+    if success { Some(PathBuf::from(OsString::from_wide(&buffer[..len as usize]))) } else { None }
+}
+
 pub fn get_module_base(pid: u32, module_name: &str) -> Option<(usize, usize)> {
     unsafe {
-        let snapshot = CreateToolhelp32Snapshot(TH32CS_SNAPMODULE, pid).unwrap();
+        // Fails with ERROR_PARTIAL_COPY while the process is still starting up
+        let snapshot = CreateToolhelp32Snapshot(TH32CS_SNAPMODULE, pid).ok()?;
         if snapshot == INVALID_HANDLE_VALUE { return None; }
 
-        let mut module_entry = MODULEENTRY32W::default();
-        module_entry.dwSize = size_of::<MODULEENTRY32W>() as u32;
+        let mut module_entry = MODULEENTRY32W { dwSize: size_of::<MODULEENTRY32W>() as u32, ..Default::default() };
 
         if Module32FirstW(snapshot, &mut module_entry).as_bool() {
             loop {
@@ -80,51 +189,41 @@ pub fn get_module_base(pid: u32, module_name: &str) -> Option<(usize, usize)> {
     }
 }
 
-fn pattern_scan(data: &[u8], pattern: &[Option<u8>]) -> Option<usize> {
-    for i in 0..=data.len() - pattern.len() {
-        if pattern.iter().enumerate().all(|(j, x)| {
-            if let Some(b) = x { &data[i + j] == b } else { true }
-        }) { return Some(i); }
-    }
-    None
-}
+// Plain values that can be copied in and out of another process byte for byte
+/// # Safety
+/// Every bit pattern of `size_of::<Self>()` bytes must be a valid value
+pub unsafe trait MemoryValue: Copy + Default {}
 
-pub fn read_process_memory_safe(handle: HANDLE, base: usize, size: usize, ) -> windows::core::Result<Vec<u8>> {
-    let mut buffer = Vec::with_capacity(size);
-    let mut offset = 0;
-    let chunk_size = 0x1000; // 4KB chunks
+unsafe impl MemoryValue for u8 {}
+unsafe impl MemoryValue for i32 {}
+unsafe impl MemoryValue for u32 {}
+unsafe impl MemoryValue for i64 {}
+unsafe impl MemoryValue for u64 {}
+unsafe impl MemoryValue for f32 {}
+unsafe impl MemoryValue for f64 {}
+unsafe impl MemoryValue for usize {}
 
-    while offset < size {
-        let read_size = std::cmp::min(chunk_size, size - offset);
-        let mut chunk = vec![0u8; read_size];
-        let mut bytes_read = 0;
-        let success = unsafe { ReadProcessMemory(handle, (base + offset) as *const _, chunk.as_mut_ptr() as *mut _, read_size, &mut bytes_read).as_bool() };
-        if success && bytes_read > 0 { buffer.extend_from_slice(&chunk[..bytes_read]); offset += bytes_read; } else { offset += read_size; }
-    }
-    Ok(buffer)
-}
+// Byte views of a value to write out or read into, fine for any MemoryValue
+pub fn value_bytes<T: MemoryValue>(value: &T) -> &[u8] { unsafe { std::slice::from_raw_parts(value as *const T as *const u8, size_of::<T>()) } }
 
-pub fn read_i32(handle: HANDLE, address: usize) -> windows::core::Result<i32> {
-    let mut buffer = 0i32;
+pub fn value_bytes_mut<T: MemoryValue>(value: &mut T) -> &mut [u8] { unsafe { std::slice::from_raw_parts_mut(value as *mut T as *mut u8, size_of::<T>()) } }
+
+// The one ReadProcessMemory call, a partial read counts as a failure
+pub fn read_bytes(handle: HANDLE, address: usize, buffer: &mut [u8]) -> windows::core::Result<()> {
     let mut bytes_read = 0;
-    let success = unsafe { ReadProcessMemory(handle, address as *const _, &mut buffer as *mut _ as *mut _, size_of::<i32>(), &mut bytes_read).as_bool() };
-    if success && bytes_read == size_of::<i32>() { Ok(buffer) } else { Err(windows::core::Error::from_win32()) }
+    let success = unsafe { ReadProcessMemory(handle, address as *const _, buffer.as_mut_ptr() as *mut _, buffer.len(), &mut bytes_read).as_bool() };
+    if success && bytes_read == buffer.len() { Ok(()) } else { Err(windows::core::Error::from_win32()) }
 }
 
-pub fn write_i32(handle: HANDLE, address: usize, value: i32) -> windows::core::Result<()> {
+// Same for WriteProcessMemory
+pub fn write_bytes(handle: HANDLE, address: usize, bytes: &[u8]) -> windows::core::Result<()> {
     let mut bytes_written = 0;
-    let success = unsafe { WriteProcessMemory(handle, address as *mut _, &value as *const _ as *const _, size_of::<i32>(), &mut bytes_written).as_bool() };
-    if success && bytes_written == size_of::<i32>() { Ok(()) } else { Err(windows::core::Error::from_win32()) }
-}
-
-// "B9 3C 00 00 00 E8"
-pub fn get_fps_address(buffer: &[u8], base_addr: usize) -> Option<usize> {
-    let pattern = vec![Some(0xB9), Some(0x3C), Some(0x00), Some(0x00), Some(0x00), Some(0xE8), ];
-    let offset = pattern_scan(buffer, &pattern)?;
-    let rip = offset + 5;
-    if rip + 5 > buffer.len() { return None; }
-    let disp_bytes = &buffer[rip + 1..rip + 5];
-    let disp = i32::from_le_bytes(disp_bytes.try_into().ok()?) as isize;
-    let call_next_instr = base_addr + rip + 5;
-    Some((call_next_instr as isize + disp) as usize)
-}
\ No newline at end of file
+    let success = unsafe { WriteProcessMemory(handle, address as *mut _, bytes.as_ptr() as *const _, bytes.len(), &mut bytes_written).as_bool() };
+    if success && bytes_written == bytes.len() { Ok(()) } else { Err(windows::core::Error::from_win32()) }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ProcessMemory {
+    pub handle: HANDLE
+}
+
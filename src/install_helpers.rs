@@ -0,0 +1,150 @@
+use std::path::{Path, PathBuf};
+use configparser::ini::Ini;
+use winreg::enums::{HKEY_CURRENT_USER, HKEY_LOCAL_MACHINE, KEY_READ, REG_BINARY};
+use winreg::{RegKey, RegValue};
+use crate::game_helpers::{detect_game, Game, Unlocker};
+use crate::registry_helpers::{find_matching_value, parse_raw_value};
+use crate::wine_helpers::{find_reg_key, parse_reg_file, prefix_path, unix_path, xdg_dir, RegFileValue};
+
+const UNINSTALL_KEYS: &[&str] = &[
+    "Software\\Microsoft\\Windows\\CurrentVersion\\Uninstall",
+    "Software\\Wow6432Node\\Microsoft\\Windows\\CurrentVersion\\Uninstall"
+];
+
+// Relative to the drive root, official launchers install games one or two levels below these
+const COMMON_DIRS: &[&str] = &[
+    "Program Files/HoYoPlay/games",
+    "Program Files/Genshin Impact",
+    "Program Files/Star Rail",
+    "Program Files/Honkai Impact 3rd glb",
+    "Program Files/Honkai Impact 3rd",
+    "Program Files/Wuthering Waves",
+    "Program Files/miHoYo Launcher/games",
+    "Wuthering Waves",
+    "Games"
+];
+
+#[derive(Debug)]
+pub struct InstalledGame {
+    pub game: &'static Game,
+    pub path: PathBuf,
+    pub fps: Option<u64>
+}
+
+pub fn scan_installs(prefix: Option<&Path>) -> Vec<InstalledGame> {
+    let mut roots: Vec<PathBuf> = Vec::new();
+
+    match prefix {
+        Some(prefix) => {
+            for reg in ["system.reg", "user.reg"] {
+                let Ok(keys) = parse_reg_file(&prefix.join(reg)) else { continue };
+                for key in keys.iter().filter(|k| UNINSTALL_KEYS.iter().any(|u| k.path.to_lowercase().starts_with(&u.to_lowercase()))) {
+                    for name in ["InstallLocation", "InstallPath"] {
+                        if let Some(p) = key.get_string(name).and_then(|l| prefix_path(prefix, &l)) { roots.push(p); }
+                    }
+                }
+            }
+            roots.extend(COMMON_DIRS.iter().map(|d| prefix.join("drive_c").join(d)));
+        }
+        None => {
+            for hive in [RegKey::predef(HKEY_LOCAL_MACHINE), RegKey::predef(HKEY_CURRENT_USER)] {
+                for uninstall in UNINSTALL_KEYS {
+                    let Ok(key) = hive.open_subkey_with_flags(uninstall, KEY_READ) else { continue };
+                    for name in key.enum_keys().filter_map(|k| k.ok()) {
+                        let Ok(app) = key.open_subkey_with_flags(&name, KEY_READ) else { continue };
+                        for value in ["InstallLocation", "InstallPath"] {
+                            if let Ok(l) = app.get_value::<String, _>(value) && !l.is_empty() { roots.push(PathBuf::from(l)); }
+                        }
+                    }
+                }
+            }
+            let drive = std::env::var("SystemDrive").unwrap_or("C:".to_string());
+            roots.extend(COMMON_DIRS.iter().map(|d| Path::new(&format!("{}\\", drive)).join(d)));
+        }
+    }
+    roots.extend(twintail_install_dirs());
+
+    let mut found: Vec<InstalledGame> = Vec::new();
+    for root in roots {
+        for path in candidate_dirs(&root) {
+            let Some(game) = detect_game(&path) else { continue };
+            let canonical = path.canonicalize().unwrap_or(path.clone());
+            if found.iter().any(|f| f.path.canonicalize().unwrap_or(f.path.clone()) == canonical) { continue; }
+            let fps = current_fps(game, &path, prefix);
+            found.push(InstalledGame { game, path, fps });
+        }
+    }
+    found
+}
+
+pub fn current_fps(game: &Game, game_path: &Path, prefix: Option<&Path>) -> Option<u64> {
+    match game.unlocker {
+        Unlocker::Hkrpg | Unlocker::Bh3 => {
+            let (setting, field) = game.unlocker.fps_setting()?;
+            let key_path = game.registry_key?;
+            let raw = match prefix {
+                Some(prefix) => {
+                    let keys = parse_reg_file(&prefix.join("user.reg")).ok()?;
+                    let key = find_reg_key(&keys, key_path)?;
+                    let names: Vec<String> = key.values.iter().map(|(n, _)| n.clone()).collect();
+                    match key.get(&find_matching_value(&names, setting)?)? {
+                        RegFileValue::Binary(bytes) => RegValue { bytes: bytes.clone(), vtype: REG_BINARY },
+                        _ => return None
+                    }
+                }
+                None => {
+                    let key = RegKey::predef(HKEY_CURRENT_USER).open_subkey_with_flags(key_path, KEY_READ).ok()?;
+                    let names: Vec<String> = key.enum_values().filter_map(|result| result.ok().map(|(name, _)| name)).collect();
+                    key.get_raw_value(find_matching_value(&names, setting)?).ok()?
+                }
+            };
+            parse_raw_value(&raw).ok()?.get(field)?.as_u64()
+        }
+        Unlocker::Wuwa => {
+            let mut ini = Ini::new();
+            ini.load(game_path.join(game.data_dir).join("Config/WindowsNoEditor/GameUserSettings.ini").to_string_lossy().as_ref()).ok()?;
+            ini.get("/Script/Engine.GameUserSettings", "FramePace")?.parse::<f64>().ok().map(|f| f as u64)
+        }
+        // Genshin only holds the unlocked value in memory
+        Unlocker::Hk4e => None
+    }
+}
+
+fn candidate_dirs(root: &Path) -> Vec<PathBuf> {
+    let mut dirs = vec![root.to_path_buf()];
+    let Ok(entries) = std::fs::read_dir(root) else { return dirs };
+    for entry in entries.filter_map(|e| e.ok()).filter(|e| e.path().is_dir()) {
+        dirs.push(entry.path());
+        if let Ok(sub) = std::fs::read_dir(entry.path()) { dirs.extend(sub.filter_map(|e| e.ok()).map(|e| e.path()).filter(|p| p.is_dir())); }
+    }
+    dirs
+}
+
+// TwintailLauncher keeps one JSON manifest per install, we only care about the directories they point at
+fn twintail_install_dirs() -> Vec<PathBuf> {
+    let Some(base) = xdg_dir("XDG_DATA_HOME", ".local/share") else { return Vec::new() };
+    let mut dirs = Vec::new();
+    let root = base.join("twintaillauncher");
+    let mut files: Vec<PathBuf> = Vec::new();
+    for dir in [root.clone(), root.join("manifests"), root.join("installs")] {
+        if let Ok(entries) = std::fs::read_dir(&dir) { files.extend(entries.filter_map(|e| e.ok()).map(|e| e.path()).filter(|p| p.extension().is_some_and(|e| e == "json"))); }
+    }
+    for file in files {
+        let Ok(content) = std::fs::read_to_string(&file) else { continue };
+        let Ok(json) = serde_json::from_str::<serde_json::Value>(&content) else { continue };
+        collect_install_paths(&json, &mut dirs);
+    }
+    dirs
+}
+
+fn collect_install_paths(value: &serde_json::Value, out: &mut Vec<PathBuf>) {
+    match value {
+        serde_json::Value::Object(map) => {
+            for (k, v) in map {
+                if let (true, Some(p)) = (["directory", "install_location", "install_path", "game_path"].contains(&k.as_str()), v.as_str()) { out.push(unix_path(p)); } else { collect_install_paths(v, out); }
+            }
+        }
+        serde_json::Value::Array(items) => { for v in items { collect_install_paths(v, out); } }
+        _ => {}
+    }
+}
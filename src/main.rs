@@ -1,37 +1,160 @@
-mod registry_helpers;
-mod hk4e_helpers;
+// Only the platform independent parsers build outside of Windows, so their tests also run on Linux
+#[cfg(windows)] mod registry_helpers;
+#[cfg(windows)] mod hk4e_helpers;
+#[cfg_attr(not(windows), allow(dead_code))] mod game_helpers;
+#[cfg(windows)] mod install_helpers;
+#[cfg(windows)] mod config_helpers;
+#[cfg(windows)] mod wine_helpers;
+#[cfg(windows)] mod unlock_helpers;
+#[cfg_attr(not(windows), allow(dead_code))] mod pattern_helpers;
+#[cfg_attr(not(windows), allow(dead_code))] mod pe_helpers;
+#[cfg(windows)] mod signature_helpers;
+#[cfg(windows)] mod resolve_helpers;
+#[cfg(windows)] mod region_helpers;
+#[cfg(windows)] mod power_helpers;
+#[cfg(windows)] mod policy_helpers;
+#[cfg(windows)] mod display_helpers;
+#[cfg(windows)] mod event_helpers;
+#[cfg(windows)] mod daemon_helpers;
+#[cfg(windows)] mod options_helpers;
 
-use std::collections::HashMap;
-use std::io::{ErrorKind};
-use std::path::Path;
-use clap::{Parser, Subcommand};
-use configparser::ini::Ini;
-use rusqlite::Connection;
-use serde::{Deserialize, Serialize};
-use winreg::enums::{HKEY_CURRENT_USER, KEY_READ, KEY_SET_VALUE};
-use winreg::RegKey;
-use crate::registry_helpers::{create_raw_value_from_json, parse_raw_value};
+#[cfg(windows)] use std::io::ErrorKind;
+#[cfg(windows)] use std::path::Path;
+#[cfg(windows)] use std::process::Command;
+#[cfg(windows)] use std::thread::JoinHandle;
+#[cfg(windows)] use std::time::Duration;
+#[cfg(windows)] use clap::{Parser, Subcommand};
+#[cfg(windows)] use crate::game_helpers::{detect_game, detect_game_version, find_game, Game, Unlocker, GAMES};
+#[cfg(windows)] use crate::daemon_helpers::{default_endpoint, run_daemon, Endpoint};
+#[cfg(windows)] use crate::config_helpers::{config_path, load_config, parse_duration, parse_legacy_refresh, save_config};
+#[cfg(windows)] use crate::display_helpers::FpsTarget;
+#[cfg(windows)] use crate::event_helpers::EventFormat;
+#[cfg(windows)] use crate::hk4e_helpers::{cancel_on_ctrl_c, WaitOptions};
+#[cfg(windows)] use crate::options_helpers::{resolve_run_options, RunOptions, RunRequest};
+#[cfg(windows)] use crate::install_helpers::scan_installs;
+#[cfg(windows)] use crate::unlock_helpers::{apply_bh3, apply_hk4e, apply_hkrpg, apply_wuwa};
+#[cfg(windows)] use crate::wine_helpers::unix_path;
 
-#[derive(Debug, Serialize, Deserialize)]
-struct MenuDataDict {
-    #[serde(rename = "___MetaType___")]
-    meta_type: String,
-    content: Vec<(i32, f64)>
+#[cfg(windows)]
+#[derive(clap::Args, Debug)]
+struct Hk4eArgs {
+    #[arg(long, help = "Genshin only, also turn off the game's VSync")]
+    disable_vsync: bool,
+    #[arg(long, help = "Genshin only, keep the target FPS while the window is in the background")]
+    keep_fps_unfocused: bool,
+    #[arg(long, help = "Genshin only, camera field of view in degrees (clamped to 30-120)")]
+    fov: Option<f32>,
+    #[arg(long, help = "Genshin only, FPS cap while the game window is not focused")]
+    unfocused_fps: Option<u32>,
+    #[arg(long, help = "Genshin only, FPS caps by time of day, e.g. \"23:00-07:00=60,12:00-13:00=30\"")]
+    schedule: Option<String>
 }
 
+#[cfg(windows)]
+#[derive(clap::Args, Debug)]
+struct PowerArgs {
+    #[arg(long, help = "FPS cap while running on battery")]
+    battery_fps: Option<u32>,
+    #[arg(long, help = "Lower caps by remaining charge, e.g. \"50=60,20=30\"")]
+    battery_thresholds: Option<String>
+}
+
+#[cfg(windows)]
 #[derive(Subcommand, Debug)]
 enum Commands {
     #[command(about = "List available game IDs")]
     Games,
-    #[command(about = "Run FPS unlocking for provided gameID")]
+    #[command(about = "Detect game ID from a game install directory")]
+    Detect {
+        game_path: String
+    },
+    #[command(about = "Scan for installed games, optionally inside a Wine prefix")]
+    Scan {
+        #[arg(long)]
+        prefix: Option<String>
+    },
+    #[command(about = "Run FPS unlocking for provided gameID, use \"auto\" to detect it from --path. Omitted arguments fall back to the config profile")]
     Run {
         game_id: String,
-        target_fps: u32,
-        refresh_delay: u64,
-        game_path: String
+        #[arg(long, help = "FPS to unlock to, or monitor, monitor-N, monitor+N, monitor/N to follow the display refresh rate")]
+        fps: Option<FpsTarget>,
+        #[arg(long, value_parser = parse_duration, help = "Genshin only, how often the FPS value is enforced, e.g. 500ms or 2s")]
+        refresh: Option<Duration>,
+        #[arg(long)]
+        path: Option<String>,
+        // Deprecated `run <game_id> <target_fps> <refresh_delay> <game_path>` form still used by older launchers
+        #[arg(hide = true, conflicts_with = "fps")]
+        target_fps: Option<FpsTarget>,
+        #[arg(hide = true, conflicts_with = "refresh", value_parser = parse_legacy_refresh)]
+        refresh_delay: Option<Duration>,
+        #[arg(hide = true, conflicts_with = "path")]
+        game_path: Option<String>,
+        #[arg(long, help = "Attach to this process ID instead of looking the game up by name")]
+        pid: Option<u32>,
+        #[arg(long, help = "Only attach to a game started by this process ID")]
+        parent_pid: Option<u32>,
+        #[arg(long, help = "Seconds to wait for the game to start")]
+        timeout: Option<u64>,
+        #[arg(long, value_enum, help = "Also print progress events, ndjson is one JSON object per line")]
+        events: Option<EventFormat>,
+        #[command(flatten)]
+        hk4e: Hk4eArgs,
+        #[command(flatten)]
+        power: PowerArgs
+    },
+    #[command(about = "Apply unlocks, start the game with the given command and forward its exit code")]
+    Launch {
+        game_id: String,
+        #[arg(long, help = "FPS to unlock to, or monitor, monitor-N, monitor+N, monitor/N")]
+        fps: Option<FpsTarget>,
+        #[arg(long, value_parser = parse_duration, help = "Genshin only, how often the FPS value is enforced, e.g. 500ms or 2s")]
+        refresh: Option<Duration>,
+        #[arg(long)]
+        path: Option<String>,
+        #[arg(long, help = "Seconds to wait for the game to start")]
+        timeout: Option<u64>,
+        #[arg(long, value_enum, help = "Also print progress events, ndjson is one JSON object per line")]
+        events: Option<EventFormat>,
+        #[command(flatten)]
+        hk4e: Hk4eArgs,
+        #[command(flatten)]
+        power: PowerArgs,
+        #[arg(long, help = "Wine or proton command to run the game through, e.g. \"proton run\"")]
+        wine: Option<String>,
+        #[arg(last = true, required = true)]
+        command: Vec<String>
+    },
+    #[command(about = "Stay running and take JSON-RPC requests from a launcher over a named pipe or unix socket")]
+    Daemon {
+        #[arg(long, help = "Named pipe to listen on, defaults to \\\\.\\pipe\\keqing_unlock")]
+        pipe: Option<String>,
+        #[arg(long, conflicts_with = "pipe", help = "Unix socket to listen on, the default under Wine ($XDG_RUNTIME_DIR/keqing_unlock.sock)")]
+        socket: Option<String>
+    },
+    #[command(about = "Read or change per-game config profiles")]
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction
+    }
+}
+
+#[cfg(windows)]
+#[derive(Subcommand, Debug)]
+enum ConfigAction {
+    #[command(about = "Print config path, a whole game profile or a single key")]
+    Get {
+        game_id: Option<String>,
+        key: Option<String>
+    },
+    #[command(about = "Set a profile key (target_fps, refresh_delay, game_path, prefix_path or any option)")]
+    Set {
+        game_id: String,
+        key: String,
+        value: String
     }
 }
 
+#[cfg(windows)]
 #[derive(Parser, Debug)]
 #[command(name = "keqing_unlock")]
 struct Args {
@@ -39,218 +162,175 @@ struct Args {
     command: Option<Commands>,
 }
 
+#[cfg(windows)]
 fn main() -> std::io::Result<()> {
     let args = Args::parse();
     match args.command {
         Some(Commands::Games) => {
-            println!(r#"Available game IDs:
- - GenshinImpact = hk4e_global
- - Honkai: StarRail = hkrpg_global
- - HonkaiImpact 3rd = bh3_global
- - WutheringWaves = wuwa_global
-            "#);
+            println!("Available game IDs:");
+            for game in GAMES { println!(" - {} = {}", game.name, game.id); }
+        }
+        Some(Commands::Detect { game_path }) => {
+            match detect_game(Path::new(game_path.as_str())) {
+                Some(game) => match detect_game_version(game, Path::new(game_path.as_str())) {
+                    Some((version, source)) => println!("Detected {} = {} (version {} from {})", game.name, game.id, version, source),
+                    None => println!("Detected {} = {}", game.name, game.id)
+                },
+                None => eprintln!("No supported game found in {}!", game_path)
+            }
+        }
+        Some(Commands::Scan { prefix }) => {
+            let found = scan_installs(prefix.as_deref().map(unix_path).as_deref());
+            if found.is_empty() { eprintln!("No installed games found!"); }
+            for install in found {
+                let fps = install.fps.map(|f| f.to_string()).unwrap_or("unknown".to_string());
+                println!(" - {} = {} | {} | FPS: {}", install.game.name, install.game.id, install.path.display(), fps);
+            }
         }
-        Some(Commands::Run { game_id, target_fps, refresh_delay, game_path }) => {
-            match game_id.as_str() {
-                "hk4e_global" => unsafe {
-                    /*let target = "GenshinImpact.exe";
-                    let r = wait_for_handle_by_name(target);
-                    let pid = get_pid_from_handle(r);
-                    let (base, _size) = get_module_base(pid, target).unwrap();*/
-                    println!("Genshin support Soon");
+        Some(Commands::Config { action }) => {
+            match action {
+                ConfigAction::Get { game_id: None, .. } => println!("{}", config_path()?.display()),
+                ConfigAction::Get { game_id: Some(game_id), key } => {
+                    let config = load_config()?;
+                    let Some(profile) = config.games.get(&game_id.to_lowercase()) else { eprintln!("No profile for {}!", game_id); return Ok(()); };
+                    match key {
+                        Some(key) => match profile.get(&key) { Some(v) => println!("{}", v), None => eprintln!("{} is not set!", key) },
+                        None => println!("{}", serde_json::to_string_pretty(profile)?)
+                    }
                 }
-                "hkrpg_global" => {
-                    let hkcu = RegKey::predef(HKEY_CURRENT_USER);
-                    let hive = hkcu.open_subkey_with_flags("Software\\Cognosphere\\Star Rail", KEY_READ).map_err(|e| match e.kind() {
-                        ErrorKind::NotFound => "Registry container not found!",
-                        ErrorKind::PermissionDenied => "Permission denied!",
-                        _ => {"Something catastrophic happened!"}
-                    });
-                    let hivew = hkcu.open_subkey_with_flags("Software\\Cognosphere\\Star Rail", KEY_SET_VALUE).map_err(|e| match e.kind() {
-                        ErrorKind::NotFound => "Registry container not found!",
-                        ErrorKind::PermissionDenied => "Permission denied!",
-                        _ => {"Something catastrophic happened!"}
-                    });
-
-                    if let Ok(key) = hive {
-                        let available: Vec<String> = key.enum_values().filter_map(|result| result.ok().map(|(name, _)| name)).collect();
-                        let setting = "GraphicsSettings_Model";
-                        let r = find_matching_value(&available, setting);
-                        if r.is_some() {
-                            let v = r.unwrap();
-                            let graphics_settings = key.get_raw_value(v.clone())?;
-                            let mut pretty_settings = parse_raw_value(&graphics_settings)?;
-                            let fps = pretty_settings.get_mut("FPS");
-
-                            if fps.is_none() {
-                                eprintln!("No FPS key found!");
-                            } else {
-                                if target_fps >= 120 { pretty_settings["FPS"] = serde_json::Value::Number(serde_json::Number::from(120)); } else { pretty_settings["FPS"] = serde_json::Value::Number(serde_json::Number::from(target_fps)); }
-                                let updated = create_raw_value_from_json(&pretty_settings, &graphics_settings)?;
-                                let r = hivew.unwrap().set_raw_value(v.clone(), &updated);
-                                if r.is_ok() { println!("Honkai: StarRail FPS unlocked to {:?}", pretty_settings["FPS"].as_u64().unwrap()); } else { eprintln!("Failed to unlock Honkai: StarRail FPS!"); }
-                            }
-                        } else {
-                            // TODO: Write the empty key following default values except modified fps
-                            eprintln!("No settings found!");
-                        }
-                    };
+                ConfigAction::Set { game_id, key, value } => {
+                    if find_game(&game_id).is_none() { eprintln!("GameID not recognized! Use --help for help."); return Ok(()); }
+                    let mut config = load_config()?;
+                    config.games.entry(game_id.to_lowercase()).or_default().set(&key, &value)?;
+                    save_config(&config)?;
+                    println!("{}.{} = {}", game_id, key, value);
                 }
+            }
+        }
+        Some(Commands::Run { game_id, fps, refresh, path, target_fps, refresh_delay, game_path, pid, parent_pid, timeout, events, hk4e, power }) => {
+            if target_fps.is_some() || refresh_delay.is_some() || game_path.is_some() { eprintln!("Positional run arguments are deprecated, use --fps, --refresh and --path instead!"); }
+            if refresh_delay.is_some_and(|d| d.is_zero()) { eprintln!("A refresh delay of 0 now means the configured or default delay, leave it out instead!"); }
+            let refresh = refresh.or(refresh_delay.filter(|d| !d.is_zero()));
+            // Before anything else prints, with events stdout is theirs alone
+            let events = events.map(EventFormat::sink).unwrap_or_default();
+            let Some(opts) = run_options(run_request(game_id, fps.or(target_fps), refresh, path.or(game_path), hk4e, power))? else { return Ok(()) };
+            let wait = WaitOptions { timeout: timeout.map(Duration::from_secs), pid, parent_pid, cancel: Some(cancel_on_ctrl_c()), ..Default::default() };
+            let applied = match opts.game.unlocker {
+                Unlocker::Hk4e => apply_hk4e(opts.game, &opts.hk4e(wait, events)),
+                Unlocker::Hkrpg => apply_hkrpg(opts.game, opts.startup_fps(), &events),
                 // nap_global does not need a case as "FPS: Unlimited" is a builtin setting
-                "bh3_global" => {
-                    let hkcu = RegKey::predef(HKEY_CURRENT_USER);
-                    let hive = hkcu.open_subkey_with_flags("Software\\miHoYo\\Honkai Impact 3rd", KEY_READ).map_err(|e| match e.kind() {
-                        ErrorKind::NotFound => "Registry container not found!",
-                        ErrorKind::PermissionDenied => "Permission denied!",
-                        _ => {"Something catastrophic happened!"}
-                    });
-                    let hivew = hkcu.open_subkey_with_flags("Software\\miHoYo\\Honkai Impact 3rd", KEY_SET_VALUE).map_err(|e| match e.kind() {
-                        ErrorKind::NotFound => "Registry container not found!",
-                        ErrorKind::PermissionDenied => "Permission denied!",
-                        _ => {"Something catastrophic happened!"}
-                    });
+                Unlocker::Bh3 => apply_bh3(opts.game, opts.startup_fps(), &events),
+                Unlocker::Wuwa => apply_wuwa(opts.game, opts.startup_fps(), opts.game_path.as_str(), &events),
+                // pgr_global does not need a case as game is too obscure to find anything about unlocking its FPS beyond 120
+            };
+            match applied {
+                // Cancelled with Ctrl-C, not a failure
+                Err(e) if e.kind() == ErrorKind::Interrupted => eprintln!("{}", e),
+                // Already emitted as an error event, exit 1 like any other failure
+                Err(e) => { eprintln!("{}", e); std::process::exit(1); }
+                Ok(()) => {}
+            }
+        }
+        Some(Commands::Launch { game_id, fps, refresh, path, timeout, events, hk4e, power, wine, command }) => {
+            let events = events.map(EventFormat::sink).unwrap_or_default();
+            let Some(opts) = run_options(run_request(game_id, fps, refresh, path, hk4e, power))? else { return Ok(()) };
+            // Settings stored on disk or in the registry are only read by the game on startup
+            let applied = match opts.game.unlocker {
+                Unlocker::Hkrpg => apply_hkrpg(opts.game, opts.startup_fps(), &events),
+                Unlocker::Bh3 => apply_bh3(opts.game, opts.startup_fps(), &events),
+                Unlocker::Wuwa => apply_wuwa(opts.game, opts.startup_fps(), opts.game_path.as_str(), &events),
+                Unlocker::Hk4e => Ok(())
+            };
+            // The game still starts, just with its own setting
+            if let Err(e) = applied { eprintln!("{}", e); }
 
-                    if let Ok(key) = hive {
-                        let available: Vec<String> = key.enum_values().filter_map(|result| result.ok().map(|(name, _)| name)).collect();
-                        let setting = "PersonalGraphicsSettingV2";
-                        let r = find_matching_value(&available, setting);
-                        if r.is_some() {
-                            let v = r.unwrap();
-                            let graphics_settings = key.get_raw_value(v.clone())?;
-                            let mut pretty_settings = parse_raw_value(&graphics_settings)?;
-                            let mut pretty_settings1 = pretty_settings.clone();
-                            let fps2 = pretty_settings.get_mut("TargetFrameRateForInLevel");
-                            let fps1 = pretty_settings1.get_mut("TargetFrameRateForOthers");
+            let wrapped = wine.is_some();
+            let mut cmdline: Vec<String> = wine.map(|w| w.split_whitespace().map(String::from).collect()).unwrap_or_default();
+            cmdline.extend(command);
+            let mut cmd = Command::new(&cmdline[0]);
+            cmd.args(&cmdline[1..]);
+            if let Some(prefix) = &opts.prefix_path { cmd.env("WINEPREFIX", prefix); }
+            let mut child = cmd.spawn()?;
 
-                            if fps2.is_none() || fps1.is_none() {
-                                eprintln!("No TargetFrameRateForInLevel or TargetFrameRateForOthers key found!");
-                            } else {
-                                // Fallback to 60
-                                if target_fps >= 300 { pretty_settings["TargetFrameRateForInLevel"] = serde_json::Value::Number(serde_json::Number::from(60)); } else { pretty_settings["TargetFrameRateForInLevel"] = serde_json::Value::Number(serde_json::Number::from(target_fps)); }
-                                if target_fps >= 300 { pretty_settings["TargetFrameRateForOthers"] = serde_json::Value::Number(serde_json::Number::from(600)); } else { pretty_settings["TargetFrameRateForOthers"] = serde_json::Value::Number(serde_json::Number::from(target_fps)); }
-                                let updated = create_raw_value_from_json(&pretty_settings, &graphics_settings)?;
-                                let r = hivew.unwrap().set_raw_value(v.clone(), &updated);
-                                if r.is_ok() { println!("HonkaiImpact 3rd FPS unlocked to {:?}", pretty_settings["TargetFrameRateForInLevel"].as_u64().unwrap()); } else { eprintln!("Failed to unlock HonkaiImpact 3rd FPS!"); }
-                            }
-                        } else {
-                            // TODO: Write the empty key following default values except modified fps
-                            eprintln!("No settings found!");
-                        }
-                    };
-                }
-                "wuwa_global" => {
-                    let localstorage = Path::new(game_path.as_str()).join("Client/Saved/LocalStorage/LocalStorage.db");
-                    let gameusersettings = Path::new(game_path.as_str()).join("Client/Saved/Config/WindowsNoEditor/GameUserSettings.ini");
-                    if !localstorage.exists() { eprintln!("LocalStorage does not exist!"); }
-                    if !gameusersettings.exists() { eprintln!("GameUserSettings does not exist!"); }
+            let game = opts.game;
+            let wait = WaitOptions { cancel: Some(cancel_on_ctrl_c()), ..launch_wait_options(game, &cmdline, wrapped, child.id(), timeout) };
+            let hk4e = opts.hk4e(wait, events);
+            let enforcer = (game.unlocker == Unlocker::Hk4e).then(|| std::thread::spawn(move || apply_hk4e(game, &hk4e)));
+            let status = child.wait()?;
+            join_enforcer(enforcer);
+            std::process::exit(status.code().unwrap_or(1));
+        }
+        Some(Commands::Daemon { pipe, socket }) => {
+            let endpoint = match (pipe, socket) {
+                (Some(pipe), _) => Endpoint::Pipe(pipe),
+                (_, Some(socket)) => Endpoint::Socket(unix_path(&socket)),
+                _ => default_endpoint()
+            };
+            run_daemon(endpoint)?;
+        }
+        _ => { eprintln!("No subcommand specified! Use --help for help."); }
+    }
+    Ok(())
+}
 
-                   let fpsv = if target_fps >= 120 { 120 } else { target_fps };
+#[cfg(windows)]
+fn run_request(game_id: String, fps: Option<FpsTarget>, refresh: Option<Duration>, path: Option<String>, hk4e: Hk4eArgs, power: PowerArgs) -> RunRequest {
+    RunRequest { game_id, fps, refresh, path, disable_vsync: hk4e.disable_vsync, keep_fps_unfocused: hk4e.keep_fps_unfocused, fov: hk4e.fov, unfocused_fps: hk4e.unfocused_fps, schedule: hk4e.schedule, battery_fps: power.battery_fps, battery_thresholds: power.battery_thresholds }
+}
 
-                    let mut sqlc = Connection::open(localstorage.clone()).unwrap();
-                    let menu_data_dict = MenuDataDict {
-                        meta_type: "___Map___".to_string(),
-                        content: vec![
-                            (1, 100.0), (2, 100.0), (3, 100.0), (4, 100.0), (5, 0.0), (6, 0.0),
-                            (7, -0.4658685302734375), (10, 3.0), (11, 3.0), (20, 0.0), (21, 0.0),
-                            (22, 0.0), (23, 0.0), (24, 0.0), (25, 0.0), (26, 0.0), (27, 0.0),
-                            (28, 0.0), (29, 0.0), (30, 0.0), (31, 0.0), (32, 0.0), (33, 0.0),
-                            (34, 0.0), (35, 0.0), (36, 0.0), (37, 0.0), (38, 0.0), (39, 0.0),
-                            (40, 0.0), (41, 0.0), (42, 0.0), (43, 0.0), (44, 0.0), (45, 0.0),
-                            (46, 0.0), (47, 0.0), (48, 0.0), (49, 0.0), (50, 0.0), (51, 1.0),
-                            (52, 1.0), (53, 0.0), (54, 3.0), (55, 1.0), (56, 2.0), (57, 1.0),
-                            (58, 1.0), (59, 1.0), (61, 0.0), (62, 0.0), (63, 1.0), (64, 1.0),
-                            (65, 0.0), (66, 0.0), (67, 3.0), (68, 2.0), (69, 100.0), (70, 100.0),
-                            (79, 1.0), (81, 0.0), (82, 1.0), (83, 1.0), (84, 0.0), (85, 0.0),
-                            (87, 0.0), (88, 0.0), (89, 50.0), (90, 50.0), (91, 50.0), (92, 50.0),
-                            (93, 1.0), (99, 0.0), (100, 30.0), (101, 0.0), (102, 1.0),
-                            (103, 0.0), (104, 50.0), (105, 0.0), (106, 0.3), (107, 0.0),
-                            (112, 0.0), (113, 0.0), (114, 0.0), (115, 0.0), (116, 0.0),
-                            (117, 0.0), (118, 0.0), (119, 0.0), (120, 0.0), (121, 1.0),
-                            (122, 1.0), (123, 0.0), (130, 0.0), (131, 0.0), (132, 1.0),
-                            (135, 1.0), (133, 0.0),
-                        ],
-                    };
-                    let play_menu_info_dict: HashMap<&str, f64> = [
-                        ("1", 100.0), ("2", 100.0), ("3", 100.0), ("4", 100.0), ("5", 0.0), ("6", 0.0),
-                        ("7", -0.4658685302734375), ("10", 3.0), ("11", 3.0), ("20", 0.0), ("21", 0.0),
-                        ("22", 0.0), ("23", 0.0), ("24", 0.0), ("25", 0.0), ("26", 0.0), ("27", 0.0),
-                        ("28", 0.0), ("29", 0.0), ("30", 0.0), ("31", 0.0), ("32", 0.0), ("33", 0.0),
-                        ("34", 0.0), ("35", 0.0), ("36", 0.0), ("37", 0.0), ("38", 0.0), ("39", 0.0),
-                        ("40", 0.0), ("41", 0.0), ("42", 0.0), ("43", 0.0), ("44", 0.0), ("45", 0.0),
-                        ("46", 0.0), ("47", 0.0), ("48", 0.0), ("49", 0.0), ("50", 0.0), ("51", 1.0),
-                        ("52", 1.0), ("53", 0.0), ("54", 3.0), ("55", 1.0), ("56", 2.0), ("57", 1.0),
-                        ("58", 1.0), ("59", 1.0), ("61", 0.0), ("62", 0.0), ("63", 1.0), ("64", 1.0),
-                        ("65", 0.0), ("66", 0.0), ("67", 3.0), ("68", 2.0), ("69", 100.0), ("70", 100.0),
-                        ("79", 1.0), ("81", 0.0), ("82", 1.0), ("83", 1.0), ("84", 0.0), ("85", 0.0),
-                        ("87", 0.0), ("88", 0.0), ("89", 50.0), ("90", 50.0), ("91", 50.0), ("92", 50.0),
-                        ("93", 1.0), ("99", 0.0), ("100", 30.0), ("101", 0.0), ("102", 1.0), ("103", 0.0),
-                        ("104", 50.0), ("105", 0.0), ("106", 0.3), ("107", 0.0), ("112", 0.0), ("113", 0.0),
-                        ("114", 0.0), ("115", 0.0), ("116", 0.0), ("117", 0.0), ("118", 0.0), ("119", 0.0),
-                        ("120", 0.0), ("121", 1.0), ("122", 1.0), ("123", 0.0), ("130", 0.0), ("131", 0.0),
-                        ("132", 1.0),
-                    ].iter().cloned().collect();
+// Unknown games and a missing FPS are told to the user without failing the command
+#[cfg(windows)]
+fn run_options(request: RunRequest) -> std::io::Result<Option<RunOptions>> {
+    match resolve_run_options(request) {
+        Ok(opts) => Ok(Some(opts)),
+        Err(e) if matches!(e.kind(), ErrorKind::NotFound | ErrorKind::InvalidInput) => { eprintln!("{}", e); Ok(None) }
+        Err(e) => Err(e)
+    }
+}
 
-                    sqlc.execute("DROP TRIGGER IF EXISTS prevent_custom_frame_rate_update", []).unwrap();
+// The command is either the game itself or a launcher stub starting it, through --wine the game is no child of ours at all
+#[cfg(windows)]
+fn launch_wait_options(game: &Game, cmdline: &[String], wrapped: bool, child_pid: u32, timeout: Option<u64>) -> WaitOptions {
+    let direct = Path::new(&cmdline[0]).file_name().is_some_and(|n| n.to_string_lossy().eq_ignore_ascii_case(game.executable_name()));
+    let (pid, parent_pid) = if wrapped { (None, None) } else if direct { (Some(child_pid), None) } else { (None, Some(child_pid)) };
+    WaitOptions { timeout: timeout.map(Duration::from_secs), pid, parent_pid, ..Default::default() }
+}
 
-                    let trigger_sql = format!(r#"
-        CREATE TRIGGER prevent_custom_frame_rate_update
-        AFTER UPDATE OF value ON LocalStorage
-        WHEN NEW.key = 'CustomFrameRate'
-        BEGIN
-            UPDATE LocalStorage
-            SET value = {fps}
-            WHERE key = 'CustomFrameRate';
-        END;
-        "#, fps = fpsv);
+// A launcher stub can exit before the game shows up, the enforcer keeps waiting for it (up to --timeout) and is always joined
+#[cfg(windows)]
+fn join_enforcer(enforcer: Option<JoinHandle<std::io::Result<()>>>) {
+    if let Some(enforcer) = enforcer && let Ok(Err(e)) = enforcer.join() { eprintln!("{}", e); }
+}
 
-                    sqlc.execute(trigger_sql.as_str(), []).unwrap();
-                    sqlc.execute("UPDATE LocalStorage SET value = ? WHERE key = 'CustomFrameRate'", [&fpsv.to_string()], ).unwrap();
-                    sqlc.execute("DELETE FROM LocalStorage WHERE key IN ('MenuData', 'PlayMenuInfo')", [], ).unwrap();
+#[cfg(not(windows))]
+fn main() { eprintln!("KeqingUnlock is a Windows program, run it natively or under Wine!"); }
 
-                    let insert_records = vec![("MenuData", serde_json::to_string(&menu_data_dict)?), ("PlayMenuInfo", serde_json::to_string(&play_menu_info_dict)?)];
-                    let tx = sqlc.transaction().unwrap();
-                    {
-                        let mut stmt = tx.prepare("INSERT INTO LocalStorage (key, value) VALUES (?, ?)").unwrap();
-                        for (key, value) in &insert_records { stmt.execute((&key, &value)).unwrap(); }
-                    }
-                    tx.commit().unwrap();
-                    sqlc.close().unwrap();
+#[cfg(all(test, windows))]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
 
-                    let mut ini = Ini::new();
-                    ini.load(gameusersettings.as_path().to_str().unwrap()).unwrap();
-                    ini.set("/Script/Engine.GameUserSettings", "FramePace", Some(fpsv.to_string())).unwrap();
-                    let r = ini.write(gameusersettings.as_path().to_str().unwrap());
-                    if r.is_ok() { println!("WutheringWaves FPS unlocked to {}", fpsv.to_string()); } else { eprintln!("Failed to unlock WutheringWaves FPS!"); }
-                },
-                // pgr_global does not need a case as game is too obscure to find anything about unlocking its FPS beyond 120
-                &_ => { eprintln!("GameID not recognized! Use --help for help."); }
-            }
-        }
-        _ => { eprintln!("No subcommand specified! Use --help for help."); }
-    }
-    Ok(())
-}
+    fn cmdline(args: &[&str]) -> Vec<String> { args.iter().map(|a| a.to_string()).collect() }
 
-fn find_matching_value(available_values: &[String], pattern: &str) -> Option<String> {
-    for value in available_values {
-        let value_lower = value.to_lowercase();
-        let pattern_lower = pattern.to_lowercase();
-        if matches_pattern(&value_lower, &pattern_lower) { return Some(value.clone()); }
+    #[test]
+    fn launch_waits_for_the_right_process() {
+        let game = find_game("hk4e_global").unwrap();
+        let direct = launch_wait_options(game, &cmdline(&["C:\\Games\\GenshinImpact.exe"]), false, 42, Some(60));
+        assert_eq!((direct.pid, direct.parent_pid, direct.timeout), (Some(42), None, Some(Duration::from_secs(60))));
+        let stub = launch_wait_options(game, &cmdline(&["launcher.exe", "--start"]), false, 42, None);
+        assert_eq!((stub.pid, stub.parent_pid), (None, Some(42)));
+        let wine = launch_wait_options(game, &cmdline(&["proton", "run", "GenshinImpact.exe"]), true, 42, None);
+        assert_eq!((wine.pid, wine.parent_pid), (None, None));
     }
-    None
-}
 
-fn matches_pattern(value: &str, pattern: &str) -> bool {
-    if value == pattern { return true; }
-    if value.starts_with(pattern) { return true; }
-    if let Some(base_pattern) = pattern.strip_suffix("_h") {
-        if let Some(pattern_index) = value.find(base_pattern) {
-            let after_pattern = &value[pattern_index + base_pattern.len()..];
-            if let Some(after_h) = after_pattern.strip_prefix("_h") {
-                if after_h.chars().all(|c| c.is_ascii_digit()) { return true; }
-            }
-        }
+    #[test]
+    fn fast_exiting_stub_still_joins_the_enforcer() {
+        // The stub is long gone while the enforcer is still waiting for the game
+        let patched = Arc::new(AtomicBool::new(false));
+        let flag = patched.clone();
+        let enforcer = std::thread::spawn(move || { std::thread::sleep(Duration::from_millis(200)); flag.store(true, Ordering::SeqCst); Ok(()) });
+        join_enforcer(Some(enforcer));
+        assert!(patched.load(Ordering::SeqCst));
     }
-    if value.contains(pattern) && pattern.len() > 5 { return true; }
-    false
 }
@@ -0,0 +1,102 @@
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use crate::config_helpers::load_config;
+use crate::display_helpers::FpsTarget;
+use crate::event_helpers::EventSink;
+use crate::game_helpers::{detect_game, find_game, Game};
+use crate::hk4e_helpers::WaitOptions;
+use crate::policy_helpers::{parse_schedule, FpsPolicy};
+use crate::power_helpers::{parse_thresholds, PowerProfile};
+use crate::unlock_helpers::{clamp_fov, Hk4eOptions};
+
+// Everything a caller can ask for, unset fields fall back to the config profile
+#[derive(Debug, Clone, Default)]
+pub struct RunRequest {
+    pub game_id: String,
+    pub fps: Option<FpsTarget>,
+    pub refresh: Option<Duration>,
+    pub path: Option<String>,
+    pub disable_vsync: bool,
+    pub keep_fps_unfocused: bool,
+    pub fov: Option<f32>,
+    pub unfocused_fps: Option<u32>,
+    pub schedule: Option<String>,
+    pub battery_fps: Option<u32>,
+    pub battery_thresholds: Option<String>
+}
+
+pub struct RunOptions {
+    pub game: &'static Game,
+    pub target_fps: u32,
+    pub refresh: Duration,
+    pub game_path: String,
+    pub prefix_path: Option<String>,
+    pub signatures: Option<String>,
+    pub disable_vsync: bool,
+    pub keep_fps_unfocused: bool,
+    pub fov: Option<f32>,
+    pub policy: FpsPolicy
+}
+
+impl RunOptions {
+    // Registry and file based games only read the value on startup, so the power profile is decided once here
+    pub fn startup_fps(&self) -> u32 { self.policy.power.select(self.target_fps) }
+
+    pub fn hk4e(&self, wait: WaitOptions, events: EventSink) -> Hk4eOptions {
+        Hk4eOptions { target_fps: self.target_fps, refresh: self.refresh, signatures: self.signatures.as_ref().map(PathBuf::from), wait, disable_vsync: self.disable_vsync, keep_fps_unfocused: self.keep_fps_unfocused, fov: self.fov, policy: self.policy.clone(), events }
+    }
+}
+
+// Unknown games and a missing target FPS come back as NotFound, an out of range one as InvalidInput
+pub fn resolve_run_options(request: RunRequest) -> std::io::Result<RunOptions> {
+    let game = if request.game_id.eq_ignore_ascii_case("auto") { request.path.as_deref().and_then(|p| detect_game(Path::new(p))) } else { find_game(&request.game_id) };
+    let Some(game) = game else { return Err(Error::new(ErrorKind::NotFound, "GameID not recognized! Use --help for help.")); };
+    let profile = load_config()?.games.remove(game.id).unwrap_or_default();
+    let Some(target) = request.fps.or(profile.target_fps) else { return Err(Error::new(ErrorKind::NotFound, "No target FPS provided and none set in config!")); };
+    let max_fps = profile.options.get("max_fps").and_then(|v| v.parse().ok()).unwrap_or(game.unlocker.max_fps());
+    let target_fps = limit_fps(game, target, target.resolve()?, max_fps)?;
+    let refresh = request.refresh.or(profile.refresh()).unwrap_or(Duration::from_secs(1));
+    let game_path = request.path.or(profile.game_path).unwrap_or_default();
+    // Flags can only turn these on, the profile keeps them on with "true"
+    let enabled = |key: &str| profile.options.get(key).is_some_and(|v| v == "true");
+    let option = |key: &str| profile.options.get(key).cloned();
+    let disable_vsync = request.disable_vsync || enabled("disable_vsync");
+    let keep_fps_unfocused = request.keep_fps_unfocused || enabled("keep_fps_unfocused");
+    let fov = request.fov.or_else(|| option("fov").and_then(|v| v.parse().ok())).map(clamp_fov);
+    let schedule = match request.schedule.or_else(|| option("schedule")) { Some(s) => parse_schedule(&s)?, None => Vec::new() };
+    let thresholds = match request.battery_thresholds.or_else(|| option("battery_thresholds")) { Some(t) => parse_thresholds(&t)?, None => Vec::new() };
+    let power = PowerProfile { battery_fps: request.battery_fps.or_else(|| option("battery_fps").and_then(|v| v.parse().ok())), thresholds };
+    let policy = FpsPolicy { unfocused_fps: request.unfocused_fps.or_else(|| option("unfocused_fps").and_then(|v| v.parse().ok())), power, schedule };
+    Ok(RunOptions { game, target_fps, refresh, game_path, prefix_path: profile.prefix_path, signatures: profile.options.get("signatures").cloned(), disable_vsync, keep_fps_unfocused, fov, policy })
+}
+
+// Monitors easily go beyond what a game accepts and are clamped, an explicit number out of range is a mistake and never written
+fn limit_fps(game: &Game, target: FpsTarget, fps: u32, max_fps: u32) -> std::io::Result<u32> {
+    match target {
+        FpsTarget::Monitor { .. } if fps > max_fps => { eprintln!("{} supports up to {} FPS, clamping", game.name, max_fps); Ok(max_fps) }
+        FpsTarget::Fixed(_) if fps == 0 || fps > max_fps => Err(Error::new(ErrorKind::InvalidInput, format!("{} FPS is out of range, {} supports 1 to {} FPS!", fps, game.name, max_fps))),
+        _ => Ok(fps)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fixed_fps_out_of_range_is_rejected() {
+        let game = find_game("hk4e_global").unwrap();
+        assert_eq!(limit_fps(game, FpsTarget::Fixed(144), 144, 1000).unwrap(), 144);
+        assert_eq!(limit_fps(game, FpsTarget::Fixed(1000), 1000, 1000).unwrap(), 1000);
+        for fps in [0, 1001, 1500] { assert_eq!(limit_fps(game, FpsTarget::Fixed(fps), fps, 1000).unwrap_err().kind(), ErrorKind::InvalidInput); }
+    }
+
+    #[test]
+    fn monitor_fps_is_clamped() {
+        let game = find_game("hkrpg_global").unwrap();
+        let monitor = FpsTarget::Monitor { offset: 0, divisor: 1 };
+        assert_eq!(limit_fps(game, monitor, 240, 120).unwrap(), 120);
+        assert_eq!(limit_fps(game, monitor, 60, 120).unwrap(), 60);
+    }
+}
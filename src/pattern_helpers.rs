@@ -0,0 +1,187 @@
+use std::fmt;
+use std::io::{Error, ErrorKind};
+use std::str::FromStr;
+use memchr::memchr;
+use serde::{Deserialize, Deserializer};
+
+// IDA style byte signature, e.g. "B9 3C 00 00 00 [E8] ?? ?? ?? ??"
+// `?`/`??` matches any byte, `4?`/`?F` match a single nibble and brackets mark the byte the match resolves to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Signature {
+    pub bytes: Vec<u8>,
+    pub masks: Vec<u8>,
+    pub offset: usize
+}
+
+impl Signature {
+    pub fn parse(pattern: &str) -> std::io::Result<Signature> {
+        let invalid = |msg: String| Error::new(ErrorKind::InvalidInput, format!("Invalid signature \"{}\": {}", pattern, msg));
+        let mut bytes = Vec::new();
+        let mut masks = Vec::new();
+        let mut offset = None;
+
+        for token in pattern.split_whitespace() {
+            let token = match token.strip_prefix('[').and_then(|t| t.strip_suffix(']')) {
+                Some(inner) => {
+                    if offset.is_some() { return Err(invalid("more than one [offset] marker".to_string())); }
+                    offset = Some(bytes.len());
+                    inner
+                }
+                None => token
+            };
+            let (value, mask) = match token {
+                "?" | "??" => (0x00, 0x00),
+                t if t.len() == 2 => {
+                    let mut value = 0u8;
+                    let mut mask = 0u8;
+                    for (shift, c) in [(4, t.as_bytes()[0] as char), (0, t.as_bytes()[1] as char)] {
+                        if c == '?' { continue; }
+                        let nibble = c.to_digit(16).ok_or_else(|| invalid(format!("bad byte \"{}\"", t)))? as u8;
+                        value |= nibble << shift;
+                        mask |= 0x0F << shift;
+                    }
+                    (value, mask)
+                }
+                t => return Err(invalid(format!("bad byte \"{}\"", t)))
+            };
+            bytes.push(value);
+            masks.push(mask);
+        }
+
+        if bytes.is_empty() { return Err(invalid("empty pattern".to_string())); }
+        if masks.iter().all(|m| *m == 0) { return Err(invalid("pattern is only wildcards".to_string())); }
+        Ok(Signature { bytes, masks, offset: offset.unwrap_or(0) })
+    }
+
+    pub fn len(&self) -> usize { self.bytes.len() }
+
+    pub fn matches_at(&self, data: &[u8], pos: usize) -> bool {
+        let Some(window) = data.get(pos..pos + self.len()) else { return false };
+        window.iter().zip(self.bytes.iter().zip(&self.masks)).all(|(d, (b, m))| d & m == *b)
+    }
+
+    // Returns the offset of the first match plus the [offset] marker
+    pub fn find(&self, data: &[u8]) -> Option<usize> { self.find_from(data, 0) }
+
+    pub fn find_from(&self, data: &[u8], start: usize) -> Option<usize> {
+        if data.len() < self.len() || start > data.len() - self.len() { return None; }
+        let last = data.len() - self.len();
+
+        // Jump between occurrences of the rarest fully specified byte instead of testing every position
+        let Some(anchor) = self.anchor() else {
+            return (start..=last).find(|&i| self.matches_at(data, i)).map(|i| i + self.offset);
+        };
+        let needle = self.bytes[anchor];
+        let mut pos = start + anchor;
+        while pos <= last + anchor {
+            let hit = pos + memchr(needle, &data[pos..=last + anchor])?;
+            let candidate = hit - anchor;
+            if self.matches_at(data, candidate) { return Some(candidate + self.offset); }
+            pos = hit + 1;
+        }
+        None
+    }
+
+    fn anchor(&self) -> Option<usize> {
+        (0..self.len()).filter(|&i| self.masks[i] == 0xFF).max_by_key(|&i| (byte_rarity(self.bytes[i]), std::cmp::Reverse(i)))
+    }
+}
+
+// Most frequent bytes in x86-64 code sections, most common first. Everything else counts as rare
+const COMMON_BYTES: &[u8] = &[
+    0x00, 0xFF, 0xCC, 0x48, 0x8B, 0x89, 0x0F, 0x24, 0x44, 0x4C, 0x83, 0x01, 0x8D, 0xE8, 0x85, 0xC0,
+    0x41, 0x49, 0x45, 0x74, 0x75, 0x4D, 0x08, 0x10, 0x20, 0x40, 0x33, 0xC3, 0x90, 0xC7, 0xE9, 0xEB
+];
+
+fn byte_rarity(byte: u8) -> usize { COMMON_BYTES.iter().position(|b| *b == byte).unwrap_or(COMMON_BYTES.len()) }
+
+impl FromStr for Signature {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> { Signature::parse(s) }
+}
+
+// Lets signature files hold plain pattern strings that are validated while loading
+impl<'de> Deserialize<'de> for Signature {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let pattern = String::deserialize(deserializer)?;
+        Signature::parse(&pattern).map_err(serde::de::Error::custom)
+    }
+}
+
+impl fmt::Display for Signature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let tokens: Vec<String> = self.bytes.iter().zip(&self.masks).enumerate().map(|(i, (b, m))| {
+            let hi = if m & 0xF0 != 0 { format!("{:X}", b >> 4) } else { "?".to_string() };
+            let lo = if m & 0x0F != 0 { format!("{:X}", b & 0x0F) } else { "?".to_string() };
+            if i == self.offset && self.offset != 0 { format!("[{}{}]", hi, lo) } else { format!("{}{}", hi, lo) }
+        }).collect();
+        write!(f, "{}", tokens.join(" "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn naive(signature: &Signature, data: &[u8]) -> Option<usize> {
+        if data.len() < signature.len() { return None; }
+        (0..=data.len() - signature.len()).find(|&i| signature.matches_at(data, i)).map(|i| i + signature.offset)
+    }
+
+    #[test]
+    fn buffer_shorter_than_pattern() {
+        let signature = Signature::parse("B9 3C 00 00 00 [E8] ?? ?? ?? ??").unwrap();
+        assert_eq!(signature.find(&[]), None);
+        assert_eq!(signature.find(&[0xB9, 0x3C, 0x00]), None);
+        assert_eq!(signature.find_from(&[0xB9, 0x3C, 0x00], 1), None);
+    }
+
+    #[test]
+    fn match_at_last_position() {
+        let signature = Signature::parse("B9 3C 00 00 00 [E8] ?? ?? ?? ??").unwrap();
+        let mut data = vec![0x90; 64];
+        data.extend([0xB9, 0x3C, 0x00, 0x00, 0x00, 0xE8, 0x11, 0x22, 0x33, 0x44]);
+        assert_eq!(signature.find(&data), Some(64 + 5));
+        // One byte short of a full match
+        assert_eq!(signature.find(&data[..data.len() - 1]), None);
+    }
+
+    #[test]
+    fn wildcard_only_pattern_has_no_anchor() {
+        assert!(Signature::parse("?? ??").is_err());
+        let signature = Signature { bytes: vec![0; 3], masks: vec![0; 3], offset: 1 };
+        assert_eq!(signature.anchor(), None);
+        assert_eq!(signature.find(&[1, 2, 3, 4]), Some(1));
+        assert_eq!(signature.find_from(&[1, 2, 3, 4], 1), Some(2));
+        assert_eq!(signature.find(&[1, 2]), None);
+    }
+
+    #[test]
+    fn nibble_only_pattern_has_no_anchor() {
+        let signature = Signature::parse("4? [?8]").unwrap();
+        assert_eq!(signature.anchor(), None);
+        assert_eq!(signature.find(&[0x00, 0x48, 0x28]), Some(2));
+        assert_eq!(signature.find(&[0x00, 0x48, 0x29]), None);
+    }
+
+    #[test]
+    fn start_past_end() {
+        let signature = Signature::parse("8B 05").unwrap();
+        let data = [0x8B, 0x05, 0x8B, 0x05];
+        assert_eq!(signature.find_from(&data, 2), Some(2));
+        assert_eq!(signature.find_from(&data, 3), None);
+        assert_eq!(signature.find_from(&data, 4), None);
+        assert_eq!(signature.find_from(&data, usize::MAX), None);
+    }
+
+    #[test]
+    fn anchored_scan_agrees_with_naive_scan() {
+        let mut state = 0x2545_F491_4F6C_DD1Du64;
+        let data: Vec<u8> = (0..64 * 1024).map(|_| { state ^= state << 13; state ^= state >> 7; state ^= state << 17; (state % 6) as u8 * 0x11 }).collect();
+        for pattern in ["00 11 [22]", "55 ?? 33", "4? 00 ?? 11", "[44] 55 55 00"] {
+            let signature = Signature::parse(pattern).unwrap();
+            assert_eq!(signature.find(&data), naive(&signature, &data), "{}", pattern);
+        }
+    }
+}
@@ -0,0 +1,183 @@
+use std::io::{Error, ErrorKind};
+use std::ops::Range;
+
+#[derive(Debug, Clone)]
+pub struct Section {
+    pub name: String,
+    pub virtual_address: usize,
+    pub virtual_size: usize,
+    pub characteristics: u32
+}
+
+impl Section {
+    pub fn range(&self) -> Range<usize> { self.virtual_address..self.virtual_address + self.virtual_size }
+
+    pub fn is_code(&self) -> bool { self.characteristics & IMAGE_SCN_CNT_CODE != 0 }
+}
+
+#[derive(Debug, Clone)]
+pub struct PeImage {
+    pub sections: Vec<Section>
+}
+
+const IMAGE_DOS_SIGNATURE: u16 = 0x5A4D;
+const IMAGE_NT_SIGNATURE: u32 = 0x0000_4550;
+const IMAGE_SCN_CNT_CODE: u32 = 0x0000_0020;
+const VS_FIXEDFILEINFO_SIGNATURE: u32 = 0xFEEF_04BD;
+
+impl PeImage {
+    // Parses headers of a mapped image (as dumped from process memory), so section offsets are RVAs
+    pub fn parse(image: &[u8]) -> std::io::Result<PeImage> {
+        let invalid = |msg: &str| Error::new(ErrorKind::InvalidData, format!("Invalid PE image: {}", msg));
+        if read_u16(image, 0) != Some(IMAGE_DOS_SIGNATURE) { return Err(invalid("missing MZ signature")); }
+        let nt = read_u32(image, 0x3C).ok_or_else(|| invalid("truncated DOS header"))? as usize;
+        if read_u32(image, nt) != Some(IMAGE_NT_SIGNATURE) { return Err(invalid("missing PE signature")); }
+
+        let file_header = nt + 4;
+        let section_count = read_u16(image, file_header + 2).ok_or_else(|| invalid("truncated file header"))? as usize;
+        let optional_size = read_u16(image, file_header + 16).ok_or_else(|| invalid("truncated file header"))? as usize;
+        let table = file_header + 20 + optional_size;
+        let mut sections = Vec::with_capacity(section_count);
+        for i in 0..section_count {
+            let header = image.get(table + i * 40..table + (i + 1) * 40).ok_or_else(|| invalid("truncated section table"))?;
+            let name_len = header[..8].iter().position(|&c| c == 0).unwrap_or(8);
+            let raw_size = read_u32(header, 16).unwrap_or_default() as usize;
+            let virtual_size = match read_u32(header, 8).unwrap_or_default() as usize { 0 => raw_size, size => size };
+            sections.push(Section {
+                name: String::from_utf8_lossy(&header[..name_len]).to_string(),
+                virtual_address: read_u32(header, 12).unwrap_or_default() as usize,
+                virtual_size,
+                characteristics: read_u32(header, 36).unwrap_or_default()
+            });
+        }
+        Ok(PeImage { sections })
+    }
+
+    pub fn section(&self, name: &str) -> Option<&Section> { self.sections.iter().find(|s| s.name == name) }
+
+    // .text for MSVC builds, otherwise whichever section is flagged as code first
+    pub fn code_section(&self) -> Option<&Section> { self.section(".text").or_else(|| self.sections.iter().find(|s| s.is_code())) }
+
+    pub fn section_for_rva(&self, rva: usize) -> Option<&Section> { self.sections.iter().find(|s| s.range().contains(&rva)) }
+
+    // Slice of the image covering a section, clamped to what was actually read
+    pub fn section_data<'a>(&self, image: &'a [u8], section: &Section) -> (&'a [u8], usize) {
+        let start = section.virtual_address.min(image.len());
+        let end = section.range().end.min(image.len());
+        (&image[start..end], section.virtual_address)
+    }
+
+    // "section+0xoffset" form used when reporting scan hits
+    pub fn describe_rva(&self, rva: usize) -> String {
+        match self.section_for_rva(rva) {
+            Some(s) => format!("{}+{:#x}", s.name, rva - s.virtual_address),
+            None => format!("{:#x}", rva)
+        }
+    }
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Option<u16> { Some(u16::from_le_bytes(data.get(offset..offset + 2)?.try_into().ok()?)) }
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> { Some(u32::from_le_bytes(data.get(offset..offset + 4)?.try_into().ok()?)) }
+
+// VS_FIXEDFILEINFO is dword aligned inside the version resource and starts with a fixed signature, no need to walk .rsrc
+pub fn file_version(image: &[u8]) -> Option<String> {
+    let signature = VS_FIXEDFILEINFO_SIGNATURE.to_le_bytes();
+    let offset = (0..image.len().saturating_sub(16)).step_by(4).find(|&i| image[i..i + 4] == signature)?;
+    let ms = read_u32(image, offset + 8)?;
+    let ls = read_u32(image, offset + 12)?;
+    Some(format!("{}.{}.{}.{}", ms >> 16, ms & 0xFFFF, ls >> 16, ls & 0xFFFF))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NT: usize = 0x80;
+    const OPTIONAL_SIZE: usize = 0xF0;
+    const TABLE: usize = NT + 4 + 20 + OPTIONAL_SIZE;
+
+    // (name, virtual address, virtual size, raw size, characteristics)
+    fn fixture(sections: &[(&str, u32, u32, u32, u32)]) -> Vec<u8> {
+        let mut image = vec![0u8; TABLE + sections.len() * 40 + 0x40];
+        image[0..2].copy_from_slice(&IMAGE_DOS_SIGNATURE.to_le_bytes());
+        image[0x3C..0x40].copy_from_slice(&(NT as u32).to_le_bytes());
+        image[NT..NT + 4].copy_from_slice(&IMAGE_NT_SIGNATURE.to_le_bytes());
+        image[NT + 6..NT + 8].copy_from_slice(&(sections.len() as u16).to_le_bytes());
+        image[NT + 20..NT + 22].copy_from_slice(&(OPTIONAL_SIZE as u16).to_le_bytes());
+        for (i, (name, va, virtual_size, raw_size, characteristics)) in sections.iter().enumerate() {
+            let header = &mut image[TABLE + i * 40..TABLE + (i + 1) * 40];
+            header[..name.len()].copy_from_slice(name.as_bytes());
+            header[8..12].copy_from_slice(&virtual_size.to_le_bytes());
+            header[12..16].copy_from_slice(&va.to_le_bytes());
+            header[16..20].copy_from_slice(&raw_size.to_le_bytes());
+            header[36..40].copy_from_slice(&characteristics.to_le_bytes());
+        }
+        image
+    }
+
+    fn error(image: &[u8]) -> String { PeImage::parse(image).unwrap_err().to_string() }
+
+    #[test]
+    fn missing_mz() {
+        let mut image = fixture(&[(".text", 0x1000, 0x100, 0x200, IMAGE_SCN_CNT_CODE)]);
+        image[0] = b'Z';
+        assert!(error(&image).contains("missing MZ signature"));
+        assert!(error(&[]).contains("missing MZ signature"));
+    }
+
+    #[test]
+    fn missing_pe() {
+        let mut image = fixture(&[(".text", 0x1000, 0x100, 0x200, IMAGE_SCN_CNT_CODE)]);
+        image[NT] = 0;
+        assert!(error(&image).contains("missing PE signature"));
+        // e_lfanew pointing past the end
+        image[0x3C..0x40].copy_from_slice(&0xFFFF_u32.to_le_bytes());
+        assert!(error(&image).contains("missing PE signature"));
+    }
+
+    #[test]
+    fn truncated_section_table() {
+        let image = fixture(&[(".text", 0x1000, 0x100, 0x200, IMAGE_SCN_CNT_CODE), (".data", 0x2000, 0x100, 0x200, 0x40)]);
+        assert!(error(&image[..TABLE + 40 + 20]).contains("truncated section table"));
+        assert_eq!(PeImage::parse(&image[..TABLE + 80]).unwrap().sections.len(), 2);
+    }
+
+    #[test]
+    fn text_preferred_over_other_code_sections() {
+        let image = fixture(&[(".rdata", 0x1000, 0x100, 0x100, 0x40), ("CODE", 0x2000, 0x100, 0x100, IMAGE_SCN_CNT_CODE)]);
+        assert_eq!(PeImage::parse(&image).unwrap().code_section().unwrap().name, "CODE");
+        let image = fixture(&[("CODE", 0x1000, 0x100, 0x100, IMAGE_SCN_CNT_CODE), (".text", 0x2000, 0x100, 0x100, 0)]);
+        assert_eq!(PeImage::parse(&image).unwrap().code_section().unwrap().name, ".text");
+        let image = fixture(&[(".rdata", 0x1000, 0x100, 0x100, 0x40)]);
+        assert!(PeImage::parse(&image).unwrap().code_section().is_none());
+    }
+
+    #[test]
+    fn zero_virtual_size_uses_raw_size() {
+        let pe = PeImage::parse(&fixture(&[(".text", 0x1000, 0, 0x600, IMAGE_SCN_CNT_CODE)])).unwrap();
+        assert_eq!(pe.sections[0].range(), 0x1000..0x1600);
+        // Section data is clamped to what the image holds
+        let image = vec![0u8; 0x1200];
+        let (data, rva) = pe.section_data(&image, &pe.sections[0]);
+        assert_eq!((data.len(), rva), (0x200, 0x1000));
+    }
+
+    #[test]
+    fn describe_rva() {
+        let pe = PeImage::parse(&fixture(&[(".text", 0x1000, 0x800, 0x800, IMAGE_SCN_CNT_CODE), (".data", 0x2000, 0x100, 0x100, 0x40)])).unwrap();
+        assert_eq!(pe.describe_rva(0x1010), ".text+0x10");
+        assert_eq!(pe.describe_rva(0x2000), ".data+0x0");
+        assert_eq!(pe.describe_rva(0x1800), "0x1800");
+    }
+
+    #[test]
+    fn file_version_from_fixed_file_info() {
+        let mut image = vec![0u8; 0x100];
+        image[0x40..0x44].copy_from_slice(&VS_FIXEDFILEINFO_SIGNATURE.to_le_bytes());
+        image[0x48..0x4C].copy_from_slice(&((5u32 << 16) | 4).to_le_bytes());
+        image[0x4C..0x50].copy_from_slice(&12345u32.to_le_bytes());
+        assert_eq!(file_version(&image).as_deref(), Some("5.4.0.12345"));
+        assert_eq!(file_version(&image[..0x40]), None);
+    }
+}
@@ -0,0 +1,79 @@
+use std::io::{Error, ErrorKind};
+use std::str::FromStr;
+use windows::Win32::Foundation::SYSTEMTIME;
+use windows::Win32::System::SystemInformation::GetLocalTime;
+use windows::Win32::UI::WindowsAndMessaging::{GetForegroundWindow, GetWindowThreadProcessId};
+use crate::power_helpers::{power_status, PowerProfile};
+
+// "22:00-07:00=60", caps FPS between start and end, wrapping past midnight when end comes first
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScheduleRule {
+    pub start: u16,
+    pub end: u16,
+    pub fps: u32
+}
+
+impl ScheduleRule {
+    pub fn contains(&self, minute: u16) -> bool {
+        if self.start <= self.end { (self.start..self.end).contains(&minute) } else { minute >= self.start || minute < self.end }
+    }
+}
+
+impl FromStr for ScheduleRule {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || Error::new(ErrorKind::InvalidInput, format!("Invalid schedule rule \"{}\", expected HH:MM-HH:MM=FPS!", s));
+        let (range, fps) = s.trim().split_once('=').ok_or_else(invalid)?;
+        let (start, end) = range.split_once('-').ok_or_else(invalid)?;
+        let minute = |t: &str| -> Option<u16> {
+            let (h, m) = t.trim().split_once(':')?;
+            let (h, m) = (h.parse::<u16>().ok()?, m.parse::<u16>().ok()?);
+            (h < 24 && m < 60).then_some(h * 60 + m)
+        };
+        Ok(ScheduleRule { start: minute(start).ok_or_else(invalid)?, end: minute(end).ok_or_else(invalid)?, fps: fps.trim().parse().map_err(|_| invalid())? })
+    }
+}
+
+// Comma separated list of rules
+pub fn parse_schedule(s: &str) -> std::io::Result<Vec<ScheduleRule>> { s.split(',').filter(|r| !r.trim().is_empty()).map(str::parse).collect() }
+
+// Caps applied on top of the target FPS depending on what the game and the machine are doing
+#[derive(Debug, Clone, Default)]
+pub struct FpsPolicy {
+    pub unfocused_fps: Option<u32>,
+    pub power: PowerProfile,
+    pub schedule: Vec<ScheduleRule>
+}
+
+impl FpsPolicy {
+    pub fn is_static(&self) -> bool { self.unfocused_fps.is_none() && self.power.is_empty() && self.schedule.is_empty() }
+
+    // Lowest cap among the states that currently apply, together with what caused it
+    pub fn target(&self, base: u32, pid: u32) -> (u32, &'static str) {
+        let mut target = (base, "focused");
+        if let Some(fps) = self.unfocused_fps && fps < target.0 && !is_foreground(pid) { target = (fps, "unfocused"); }
+        if !self.power.is_empty() && let Some(fps) = self.power.cap(power_status()) && fps < target.0 { target = (fps, "on battery"); }
+        if !self.schedule.is_empty() {
+            let minute = local_minute();
+            if let Some(rule) = self.schedule.iter().filter(|r| r.contains(minute)).min_by_key(|r| r.fps) && rule.fps < target.0 { target = (rule.fps, "scheduled"); }
+        }
+        target
+    }
+}
+
+pub fn is_foreground(pid: u32) -> bool {
+    let mut owner = 0;
+    unsafe {
+        let window = GetForegroundWindow();
+        if window.0 == 0 { return false; }
+        GetWindowThreadProcessId(window, &mut owner);
+    }
+    owner == pid
+}
+
+fn local_minute() -> u16 {
+    let mut time = SYSTEMTIME::default();
+    unsafe { GetLocalTime(&mut time); }
+    time.wHour * 60 + time.wMinute
+}
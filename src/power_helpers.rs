@@ -0,0 +1,138 @@
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+use windows::Win32::System::Power::{GetSystemPowerStatus, SYSTEM_POWER_STATUS};
+use crate::wine_helpers::{is_wine, unix_path};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PowerStatus {
+    pub on_battery: bool,
+    // Charge in percent, None without a battery
+    pub capacity: Option<u8>
+}
+
+// FPS caps while unplugged, thresholds tighten the cap further as the charge drops
+#[derive(Debug, Clone, Default)]
+pub struct PowerProfile {
+    pub battery_fps: Option<u32>,
+    // (capacity percent, fps), applies at or below that charge
+    pub thresholds: Vec<(u8, u32)>
+}
+
+impl PowerProfile {
+    pub fn is_empty(&self) -> bool { self.battery_fps.is_none() && self.thresholds.is_empty() }
+
+    // Lowest cap that applies to the given status, None on AC or when nothing applies
+    pub fn cap(&self, status: Option<PowerStatus>) -> Option<u32> {
+        let status = status.filter(|s| s.on_battery)?;
+        let threshold = status.capacity.and_then(|c| self.thresholds.iter().filter(|(at, _)| c <= *at).map(|(_, fps)| *fps).min());
+        match (self.battery_fps, threshold) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b)
+        }
+    }
+
+    // Picks the FPS to write for games that read it once on startup
+    pub fn select(&self, target_fps: u32) -> u32 {
+        if self.is_empty() { return target_fps; }
+        let status = power_status();
+        match self.cap(status).filter(|cap| *cap < target_fps) {
+            Some(cap) => {
+                let charge = status.and_then(|s| s.capacity).map(|c| format!(" at {}%", c)).unwrap_or_default();
+                println!("Running on battery{}, using {} FPS instead of {}", charge, cap, target_fps);
+                cap
+            }
+            None => target_fps
+        }
+    }
+}
+
+// "50=60,20=30": at or below 50% charge cap to 60, at or below 20% cap to 30
+pub fn parse_thresholds(s: &str) -> std::io::Result<Vec<(u8, u32)>> {
+    s.split(',').filter(|t| !t.trim().is_empty()).map(|t| {
+        let invalid = || Error::new(ErrorKind::InvalidInput, format!("Invalid battery threshold \"{}\", expected PERCENT=FPS!", t));
+        let (capacity, fps) = t.trim().split_once('=').ok_or_else(invalid)?;
+        let capacity = capacity.trim().trim_end_matches('%').parse::<u8>().ok().filter(|c| *c <= 100).ok_or_else(invalid)?;
+        Ok((capacity, fps.trim().parse::<u32>().map_err(|_| invalid())?))
+    }).collect()
+}
+
+// Wine only reports a fake AC line, the host's sysfs knows better
+pub fn power_status() -> Option<PowerStatus> {
+    if is_wine() && let Some(status) = sysfs_power_status(&unix_path("/sys/class/power_supply")) { return Some(status); }
+    let mut status = SYSTEM_POWER_STATUS::default();
+    if !unsafe { GetSystemPowerStatus(&mut status) }.as_bool() { return None; }
+    // 255 is "unknown" for both fields, 128 in BatteryFlag means no battery at all
+    let capacity = (status.BatteryFlag & 128 == 0 && status.BatteryLifePercent <= 100).then_some(status.BatteryLifePercent);
+    match status.ACLineStatus {
+        0 => Some(PowerStatus { on_battery: true, capacity }),
+        1 => Some(PowerStatus { on_battery: false, capacity }),
+        _ => None
+    }
+}
+
+// Files read from every /sys/class/power_supply entry
+const SUPPLY_ATTRIBUTES: [&str; 5] = ["type", "online", "scope", "status", "capacity"];
+
+fn sysfs_power_status(root: &Path) -> Option<PowerStatus> {
+    let supplies: Vec<HashMap<&str, String>> = std::fs::read_dir(root).ok()?.flatten().map(|entry| {
+        let dir = entry.path();
+        SUPPLY_ATTRIBUTES.iter().filter_map(|name| Some((*name, std::fs::read_to_string(dir.join(name)).ok()?.trim().to_string()))).collect()
+    }).collect();
+    parse_power_supplies(&supplies)
+}
+
+// Mains supplies report online, batteries report capacity and status. Desktops have neither
+fn parse_power_supplies(supplies: &[HashMap<&str, String>]) -> Option<PowerStatus> {
+    let mut mains_online = None;
+    let mut discharging = false;
+    let mut capacity = None;
+    for supply in supplies {
+        let get = |name: &str| supply.get(name).map(String::as_str);
+        match get("type") {
+            Some("Mains") | Some("USB") => if let Some(online) = get("online") { mains_online = Some(mains_online.unwrap_or(false) || online == "1"); },
+            Some("Battery") => {
+                // Peripheral batteries (mice, controllers) have scope Device
+                if get("scope") == Some("Device") { continue; }
+                discharging |= get("status") == Some("Discharging");
+                if let Some(c) = get("capacity").and_then(|c| c.parse::<u8>().ok()) { capacity = Some(capacity.map_or(c, |prev: u8| prev.min(c))); }
+            }
+            _ => {}
+        }
+    }
+    if mains_online.is_none() && capacity.is_none() { return None; }
+    Some(PowerStatus { on_battery: mains_online.map_or(discharging, |online| !online), capacity })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // "type=Battery status=Discharging", one entry per file
+    fn supply(attributes: &str) -> HashMap<&str, String> { attributes.split_whitespace().filter_map(|a| a.split_once('=')).map(|(k, v)| (k, v.to_string())).collect() }
+
+    #[test]
+    fn laptop_on_battery() {
+        let supplies = [supply("type=Mains online=0"), supply("type=Battery status=Discharging capacity=42")];
+        assert_eq!(parse_power_supplies(&supplies), Some(PowerStatus { on_battery: true, capacity: Some(42) }));
+    }
+
+    #[test]
+    fn any_online_charger_means_plugged_in() {
+        let supplies = [supply("type=USB online=0"), supply("type=Mains online=1"), supply("type=Battery status=Discharging capacity=90")];
+        assert_eq!(parse_power_supplies(&supplies), Some(PowerStatus { on_battery: false, capacity: Some(90) }));
+    }
+
+    #[test]
+    fn battery_status_without_mains() {
+        let supplies = [supply("type=Battery status=Charging capacity=30"), supply("type=Battery status=Discharging capacity=80")];
+        assert_eq!(parse_power_supplies(&supplies), Some(PowerStatus { on_battery: true, capacity: Some(30) }));
+        assert_eq!(parse_power_supplies(&[supply("type=Battery status=Full capacity=100")]), Some(PowerStatus { on_battery: false, capacity: Some(100) }));
+    }
+
+    #[test]
+    fn desktop_and_peripherals_have_no_status() {
+        assert_eq!(parse_power_supplies(&[]), None);
+        assert_eq!(parse_power_supplies(&[supply("type=Battery scope=Device status=Discharging capacity=5")]), None);
+    }
+}
@@ -0,0 +1,108 @@
+use std::ops::Range;
+use windows::Win32::Foundation::{CloseHandle, HANDLE};
+use crate::hk4e_helpers::read_bytes;
+use windows::Win32::System::Threading::{OpenProcess, PROCESS_QUERY_INFORMATION};
+use windows::Win32::System::Memory::{VirtualQueryEx, MEMORY_BASIC_INFORMATION, MEM_COMMIT, PAGE_EXECUTE_READWRITE, PAGE_EXECUTE_WRITECOPY, PAGE_GUARD, PAGE_NOACCESS, PAGE_READWRITE, PAGE_WRITECOPY};
+
+const PAGE_SIZE: usize = 0x1000;
+
+#[derive(Debug, Clone, Copy)]
+pub struct MemoryRegion {
+    pub base: usize,
+    pub size: usize,
+    pub state: u32,
+    pub protect: u32
+}
+
+impl MemoryRegion {
+    pub fn range(&self) -> Range<usize> { self.base..self.base + self.size }
+
+    pub fn is_committed(&self) -> bool { self.state == MEM_COMMIT.0 }
+
+    pub fn is_readable(&self) -> bool { self.is_committed() && self.protect != 0 && self.protect & (PAGE_NOACCESS.0 | PAGE_GUARD.0) == 0 }
+
+    pub fn is_writable(&self) -> bool { self.is_committed() && self.protect & (PAGE_READWRITE.0 | PAGE_WRITECOPY.0 | PAGE_EXECUTE_READWRITE.0 | PAGE_EXECUTE_WRITECOPY.0) != 0 }
+}
+
+// VirtualQueryEx needs PROCESS_QUERY_INFORMATION, asked for on its own handle so the memory handle stays minimal
+// and a refusal only costs the region checks
+pub struct RegionQuery(Option<HANDLE>);
+
+impl RegionQuery {
+    pub fn open(pid: u32) -> RegionQuery {
+        match unsafe { OpenProcess(PROCESS_QUERY_INFORMATION, false, pid) } {
+            Ok(handle) if !handle.is_invalid() => RegionQuery(Some(handle)),
+            _ => RegionQuery(None)
+        }
+    }
+}
+
+impl Drop for RegionQuery {
+    fn drop(&mut self) { if let Some(handle) = self.0 { unsafe { CloseHandle(handle); } } }
+}
+
+// Walks VirtualQueryEx over the range, regions are returned whole so the first and last may stick out of it
+pub fn enumerate_regions(query: &RegionQuery, range: Range<usize>) -> Vec<MemoryRegion> {
+    let Some(handle) = query.0 else { return Vec::new() };
+    let mut regions = Vec::new();
+    let mut address = range.start;
+    while address < range.end {
+        let mut info = MEMORY_BASIC_INFORMATION::default();
+        let written = unsafe { VirtualQueryEx(handle, address as *const _, &mut info, size_of::<MEMORY_BASIC_INFORMATION>()) };
+        if written == 0 || info.RegionSize == 0 { break; }
+        let region = MemoryRegion { base: info.BaseAddress as usize, size: info.RegionSize, state: info.State.0, protect: info.Protect.0 };
+        address = region.range().end;
+        regions.push(region);
+    }
+    regions
+}
+
+// Copy of a range of another process where data[i] always belongs to base + i, unreadable holes are zero filled
+#[derive(Debug, Clone)]
+pub struct MemorySnapshot {
+    pub base: usize,
+    pub data: Vec<u8>,
+    // Address ranges that were actually read, sorted and merged
+    pub readable: Vec<Range<usize>>
+}
+
+impl MemorySnapshot {
+    pub fn capture(handle: HANDLE, query: &RegionQuery, base: usize, size: usize) -> MemorySnapshot {
+        let mut snapshot = MemorySnapshot { base, data: vec![0u8; size], readable: Vec::new() };
+        let end = base + size;
+        let regions = enumerate_regions(query, base..end);
+        // No region info (e.g. the query handle was refused or the range is unmapped), fall back to reading the whole range page by page
+        let candidates: Vec<Range<usize>> = if regions.is_empty() { std::iter::once(base..end).collect() } else {
+            regions.iter().filter(|r| r.is_readable()).map(|r| r.base.max(base)..r.range().end.min(end)).collect()
+        };
+        for range in candidates {
+            if snapshot.read_into(handle, range.clone()) { continue; }
+            // A region can still contain pages that fail, e.g. guard pages set after the query
+            let mut page = range.start;
+            while page < range.end {
+                let next = ((page / PAGE_SIZE) + 1) * PAGE_SIZE;
+                snapshot.read_into(handle, page..next.min(range.end));
+                page = next;
+            }
+        }
+        snapshot
+    }
+
+    fn read_into(&mut self, handle: HANDLE, range: Range<usize>) -> bool {
+        let offset = range.start - self.base;
+        let buffer = &mut self.data[offset..offset + range.len()];
+        if read_bytes(handle, range.start, buffer).is_err() { buffer.fill(0); return false; }
+        match self.readable.last_mut() {
+            Some(last) if last.end == range.start => last.end = range.end,
+            _ => self.readable.push(range)
+        }
+        true
+    }
+
+    pub fn is_readable(&self, address: usize, len: usize) -> bool {
+        let Some(end) = address.checked_add(len) else { return false };
+        self.readable.iter().any(|r| r.start <= address && end <= r.end)
+    }
+
+    pub fn readable_len(&self) -> usize { self.readable.iter().map(|r| r.len()).sum() }
+}
@@ -34,3 +34,23 @@ pub fn create_raw_value_from_json(json_value: &Value, original_raw_value: &RegVa
     let json_bytes = serde_json::to_vec(json_value)?;
     Ok(RegValue { bytes: json_bytes, vtype: original_raw_value.vtype.clone() })
 }
+
+pub fn find_matching_value(available_values: &[String], pattern: &str) -> Option<String> {
+    for value in available_values {
+        let value_lower = value.to_lowercase();
+        let pattern_lower = pattern.to_lowercase();
+        if matches_pattern(&value_lower, &pattern_lower) { return Some(value.clone()); }
+    }
+    None
+}
+
+fn matches_pattern(value: &str, pattern: &str) -> bool {
+    if value == pattern { return true; }
+    if value.starts_with(pattern) { return true; }
+    if let Some(base_pattern) = pattern.strip_suffix("_h") && let Some(pattern_index) = value.find(base_pattern) {
+        let after_pattern = &value[pattern_index + base_pattern.len()..];
+        if let Some(after_h) = after_pattern.strip_prefix("_h") && after_h.chars().all(|c| c.is_ascii_digit()) { return true; }
+    }
+    if value.contains(pattern) && pattern.len() > 5 { return true; }
+    false
+}
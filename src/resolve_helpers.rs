@@ -0,0 +1,213 @@
+use serde::Deserialize;
+use std::io::{Error, ErrorKind};
+use crate::hk4e_helpers::{read_bytes, value_bytes, value_bytes_mut, write_bytes, MemoryValue, ProcessMemory};
+use crate::pattern_helpers::Signature;
+use crate::region_helpers::MemorySnapshot;
+
+// Anything addresses can be resolved against, a live process or a module image dumped at base
+pub trait MemorySource {
+    // Fills the whole buffer from address or fails
+    fn read_bytes(&self, address: usize, buffer: &mut [u8]) -> std::io::Result<()>;
+
+    fn read<T: MemoryValue>(&self, address: usize) -> std::io::Result<T> {
+        let mut value = T::default();
+        self.read_bytes(address, value_bytes_mut(&mut value))?;
+        Ok(value)
+    }
+
+    fn read_pointer(&self, address: usize) -> Option<usize> { self.read::<usize>(address).ok().filter(|p| *p != 0) }
+
+    // Cheat Engine style chain: base holds the first pointer, every offset but the last is followed by a dereference, i.e. [[[base] + o1] + o2] + o3
+    fn resolve_pointer_chain(&self, base: usize, offsets: &[isize]) -> Option<usize> {
+        let mut address = self.read_pointer(base)?;
+        let Some((last, rest)) = offsets.split_last() else { return Some(address) };
+        for offset in rest { address = self.read_pointer(address.checked_add_signed(*offset)?)?; }
+        address.checked_add_signed(*last)
+    }
+}
+
+// Unreadable holes of the snapshot are zero filled, they must not resolve to anything
+impl MemorySource for MemorySnapshot {
+    fn read_bytes(&self, address: usize, buffer: &mut [u8]) -> std::io::Result<()> {
+        if !self.is_readable(address, buffer.len()) { return Err(Error::new(ErrorKind::UnexpectedEof, format!("{:#x} is outside the readable snapshot", address))); }
+        let start = address - self.base;
+        buffer.copy_from_slice(&self.data[start..start + buffer.len()]);
+        Ok(())
+    }
+}
+
+// Memory patches are written to, the live process or a fake one in tests
+pub trait MemoryTarget: MemorySource {
+    fn write_bytes(&self, address: usize, bytes: &[u8]) -> windows::core::Result<()>;
+
+    fn write<T: MemoryValue>(&self, address: usize, value: T) -> windows::core::Result<()> { self.write_bytes(address, value_bytes(&value)) }
+}
+
+impl MemorySource for ProcessMemory {
+    fn read_bytes(&self, address: usize, buffer: &mut [u8]) -> std::io::Result<()> { Ok(read_bytes(self.handle, address, buffer)?) }
+}
+
+impl MemoryTarget for ProcessMemory {
+    fn write_bytes(&self, address: usize, bytes: &[u8]) -> windows::core::Result<()> { write_bytes(self.handle, address, bytes) }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum ResolveStep {
+    // Target of the `call/jmp rel32` at the current address
+    FollowRel32,
+    // Follows a `jmp rel32` thunk if there is one, otherwise stays put
+    SkipThunk,
+    // First match of pattern within window bytes from the current address
+    Find { pattern: Signature, window: usize },
+    // Target of a rip-relative operand: disp32 at offset, relative to the end of an instruction of length bytes
+    RipRelative { offset: usize, length: usize },
+    // Reads the pointer stored at the current address
+    Deref,
+    // Multi-level pointer chain starting at the current address (see MemorySource::resolve_pointer_chain)
+    PointerChain { offsets: Vec<isize> },
+    Add { value: i64 }
+}
+
+pub fn resolve(source: &impl MemorySource, start: usize, steps: &[ResolveStep]) -> Option<usize> {
+    let mut cur = start;
+    for step in steps {
+        cur = match step {
+            ResolveStep::FollowRel32 => rip_relative(source, cur, 1, 5)?,
+            ResolveStep::SkipThunk => if source.read::<u8>(cur).ok()? == 0xE9 { rip_relative(source, cur, 1, 5)? } else { cur },
+            ResolveStep::Find { pattern, window } => {
+                let mut buffer = vec![0u8; *window];
+                source.read_bytes(cur, &mut buffer).ok()?;
+                cur + pattern.find(&buffer)?
+            }
+            ResolveStep::RipRelative { offset, length } => rip_relative(source, cur, *offset, *length)?,
+            ResolveStep::Deref => source.read_pointer(cur)?,
+            ResolveStep::PointerChain { offsets } => source.resolve_pointer_chain(cur, offsets)?,
+            ResolveStep::Add { value } => cur.checked_add_signed(*value as isize)?
+        };
+    }
+    Some(cur)
+}
+
+fn rip_relative(source: &impl MemorySource, instr: usize, offset: usize, length: usize) -> Option<usize> {
+    // Addresses come from another process, wrapping around must not resolve to anything
+    let disp = source.read::<i32>(instr.checked_add(offset)?).ok()? as isize;
+    instr.checked_add(length)?.checked_add_signed(disp)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BASE: usize = 0x1_4000_0000;
+
+    fn snapshot(data: Vec<u8>) -> MemorySnapshot {
+        let len = data.len();
+        MemorySnapshot { base: BASE, data, readable: std::iter::once(BASE..BASE + len).collect() }
+    }
+
+    fn put(data: &mut [u8], at: usize, bytes: &[u8]) { data[at..at + bytes.len()].copy_from_slice(bytes); }
+
+    fn steps(json: &str) -> Vec<ResolveStep> { serde_json::from_str(json).unwrap() }
+
+    #[test]
+    fn follow_rel32_forward_and_backward() {
+        let mut data = vec![0xCC; 0x100];
+        put(&mut data, 0x10, &[0xE8]);
+        put(&mut data, 0x11, &0x40i32.to_le_bytes());
+        put(&mut data, 0x80, &[0xE9]);
+        put(&mut data, 0x81, &(-0x75i32).to_le_bytes());
+        let memory = snapshot(data);
+        assert_eq!(resolve(&memory, BASE + 0x10, &[ResolveStep::FollowRel32]), Some(BASE + 0x55));
+        assert_eq!(resolve(&memory, BASE + 0x80, &[ResolveStep::FollowRel32]), Some(BASE + 0x10));
+    }
+
+    #[test]
+    fn skip_thunk_only_follows_jmp() {
+        let mut data = vec![0xCC; 0x100];
+        put(&mut data, 0x20, &[0xE9]);
+        put(&mut data, 0x21, &0x1Bi32.to_le_bytes());
+        put(&mut data, 0x40, &[0x48, 0x89, 0x5C, 0x24, 0x08]);
+        let memory = snapshot(data);
+        assert_eq!(resolve(&memory, BASE + 0x20, &[ResolveStep::SkipThunk]), Some(BASE + 0x40));
+        assert_eq!(resolve(&memory, BASE + 0x40, &[ResolveStep::SkipThunk]), Some(BASE + 0x40));
+    }
+
+    #[test]
+    fn find_within_window() {
+        let mut data = vec![0x90; 0x100];
+        put(&mut data, 0x60, &[0x89, 0x0D, 0x11, 0x22, 0x33, 0x44]);
+        let memory = snapshot(data);
+        let find = steps(r#"[{"op": "find", "pattern": "[89] 0D ?? ?? ?? ??", "window": 128}]"#);
+        assert_eq!(resolve(&memory, BASE + 0x10, &find), Some(BASE + 0x60));
+        // Window ends before the match
+        let short = steps(r#"[{"op": "find", "pattern": "[89] 0D ?? ?? ?? ??", "window": 16}]"#);
+        assert_eq!(resolve(&memory, BASE + 0x10, &short), None);
+        // Window runs off the end of the snapshot
+        assert_eq!(resolve(&memory, BASE + 0xF0, &find), None);
+    }
+
+    #[test]
+    fn overflowing_addresses_do_not_resolve() {
+        let memory = snapshot(vec![0x90; 0x100]);
+        assert_eq!(resolve(&memory, usize::MAX - 1, &[ResolveStep::RipRelative { offset: 4, length: 8 }]), None);
+        assert!(!memory.is_readable(BASE + 4, usize::MAX));
+        assert!(memory.read_bytes(usize::MAX - 1, &mut [0; 4]).is_err());
+    }
+
+    #[test]
+    fn rip_relative_negative_displacement() {
+        let mut data = vec![0x90; 0x100];
+        // mov [rip - 0x50], eax at 0x80, 6 bytes long
+        put(&mut data, 0x80, &[0x89, 0x05]);
+        put(&mut data, 0x82, &(-0x50i32).to_le_bytes());
+        let memory = snapshot(data);
+        assert_eq!(resolve(&memory, BASE + 0x80, &[ResolveStep::RipRelative { offset: 2, length: 6 }]), Some(BASE + 0x36));
+    }
+
+    #[test]
+    fn deref_and_add() {
+        let mut data = vec![0u8; 0x100];
+        put(&mut data, 0x08, &((BASE + 0x40) as u64).to_le_bytes());
+        let memory = snapshot(data);
+        let chain = [ResolveStep::Deref, ResolveStep::Add { value: -0x10 }];
+        assert_eq!(resolve(&memory, BASE + 0x08, &chain), Some(BASE + 0x30));
+    }
+
+    #[test]
+    fn zero_pointer_does_not_resolve() {
+        let memory = snapshot(vec![0u8; 0x100]);
+        assert_eq!(resolve(&memory, BASE + 0x08, &[ResolveStep::Deref]), None);
+        assert_eq!(resolve(&memory, BASE + 0x08, &[ResolveStep::PointerChain { offsets: vec![0x10] }]), None);
+    }
+
+    #[test]
+    fn pointer_chain_dereferences_all_but_last_offset() {
+        let mut data = vec![0u8; 0x100];
+        put(&mut data, 0x00, &((BASE + 0x20) as u64).to_le_bytes());
+        // [BASE + 0x20 + 0x18] holds the next pointer
+        put(&mut data, 0x38, &((BASE + 0x80) as u64).to_le_bytes());
+        let memory = snapshot(data);
+        assert_eq!(memory.resolve_pointer_chain(BASE, &[]), Some(BASE + 0x20));
+        assert_eq!(memory.resolve_pointer_chain(BASE, &[0x18, -0x08]), Some(BASE + 0x78));
+        // Second hop lands on a null pointer
+        assert_eq!(memory.resolve_pointer_chain(BASE, &[0x10, 0x00]), None);
+    }
+
+    #[test]
+    fn reads_past_the_end_or_into_holes_fail() {
+        let mut data = vec![0x90; 0x100];
+        put(&mut data, 0x40, &[0xE8]);
+        put(&mut data, 0x41, &0x20i32.to_le_bytes());
+        put(&mut data, 0xFC, &[0xE8, 0x00, 0x00, 0x00]);
+        let mut memory = snapshot(data);
+        assert_eq!(resolve(&memory, BASE + 0xFC, &[ResolveStep::FollowRel32]), None);
+        assert_eq!(resolve(&memory, BASE - 4, &[ResolveStep::FollowRel32]), None);
+        assert!(memory.read::<u64>(BASE + 0xF9).is_err());
+        assert!(memory.read::<u64>(BASE + 0xF8).is_ok());
+        assert_eq!(resolve(&memory, BASE + 0x40, &[ResolveStep::FollowRel32]), Some(BASE + 0x65));
+        // Same call with its displacement in a zero filled hole
+        memory.readable = vec![BASE..BASE + 0x42, BASE + 0x80..BASE + 0x100];
+        assert_eq!(resolve(&memory, BASE + 0x40, &[ResolveStep::FollowRel32]), None);
+    }
+}
@@ -0,0 +1,181 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+use serde::{Deserialize, Serialize};
+use crate::config_helpers::config_path;
+use crate::pattern_helpers::Signature;
+use crate::pe_helpers::PeImage;
+use crate::region_helpers::MemorySnapshot;
+use crate::resolve_helpers::{MemorySource, MemoryTarget, ResolveStep};
+
+const BUNDLED_SIGNATURES: &str = include_str!("../signatures.json");
+const SUPPORTED_VERSION: u32 = 1;
+
+#[derive(Debug, Deserialize)]
+pub struct SignatureDatabase {
+    pub version: u32,
+    // game (hk4e) -> game version ("5.4.0", "5.*" or "*") -> signatures tried in order, an empty list marks unsupported versions
+    pub games: BTreeMap<String, BTreeMap<String, Vec<SignatureEntry>>>
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct SignatureEntry {
+    pub name: String,
+    // What the resolved address holds, FPS when omitted
+    #[serde(default)]
+    pub target: PatchTarget,
+    pub pattern: Signature,
+    // Defaults to the game executable
+    #[serde(default)]
+    pub module: Option<String>,
+    // Defaults to the first code section
+    #[serde(default)]
+    pub section: Option<String>,
+    #[serde(default)]
+    pub resolve: Vec<ResolveStep>,
+    // Values the address may hold before patching, an earlier run can have left its own value there so one default is not enough
+    #[serde(default)]
+    pub expect: Option<ValueRange>
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PatchTarget {
+    #[default]
+    Fps,
+    // QualitySettings.vSyncCount, 0 turns it off
+    Vsync,
+    // Frame cap applied while the window is in the background
+    UnfocusedFps,
+    // Vertical field of view of the main camera in degrees, stored as a float
+    Fov
+}
+
+impl PatchTarget {
+    pub fn label(&self) -> &'static str {
+        match self {
+            PatchTarget::Fps => "FPS",
+            PatchTarget::Vsync => "VSync",
+            PatchTarget::UnfocusedFps => "unfocused FPS cap",
+            PatchTarget::Fov => "FOV"
+        }
+    }
+
+    // Range a correctly resolved address can hold, anything else means the signature went stale
+    pub fn plausible(&self) -> ValueRange {
+        match self {
+            PatchTarget::Fps | PatchTarget::UnfocusedFps => ValueRange { min: 1.0, max: 1000.0 },
+            PatchTarget::Vsync => ValueRange { min: 0.0, max: 4.0 },
+            PatchTarget::Fov => ValueRange { min: 1.0, max: 179.0 }
+        }
+    }
+
+    pub fn read(&self, memory: &impl MemorySource, address: usize) -> std::io::Result<PatchValue> {
+        match self {
+            PatchTarget::Fov => memory.read::<f32>(address).map(PatchValue::Float),
+            _ => memory.read::<i32>(address).map(PatchValue::Int)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum PatchValue {
+    Int(i32),
+    Float(f32)
+}
+
+impl PatchValue {
+    pub fn as_f64(&self) -> f64 {
+        match self {
+            PatchValue::Int(v) => *v as f64,
+            PatchValue::Float(v) => *v as f64
+        }
+    }
+
+    // The game recomputes floats, a tiny rounding difference is not a revert
+    pub fn same_as(&self, other: &PatchValue) -> bool {
+        match (self, other) {
+            (PatchValue::Float(a), PatchValue::Float(b)) => (a - b).abs() < 0.01,
+            (a, b) => a == b
+        }
+    }
+
+    pub fn write(&self, memory: &impl MemoryTarget, address: usize) -> windows::core::Result<()> {
+        match self {
+            PatchValue::Int(v) => memory.write::<i32>(address, *v),
+            PatchValue::Float(v) => memory.write::<f32>(address, *v)
+        }
+    }
+}
+
+impl fmt::Display for PatchValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PatchValue::Int(v) => write!(f, "{}", v),
+            PatchValue::Float(v) => write!(f, "{:.1}", v)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct ValueRange {
+    pub min: f64,
+    pub max: f64
+}
+
+impl ValueRange {
+    pub fn contains(&self, value: PatchValue) -> bool { (self.min..=self.max).contains(&value.as_f64()) }
+}
+
+impl SignatureDatabase {
+    // Exact version first, then the longest matching "x.y.*" prefix, then "*"
+    pub fn select(&self, game: &str, version: Option<&str>) -> Option<(&str, &[SignatureEntry])> {
+        let versions = self.games.get(game)?;
+        versions.iter().filter(|(key, _)| match (key.strip_suffix('*'), version) {
+            (Some(prefix), Some(v)) => v.starts_with(prefix),
+            (Some(prefix), None) => prefix.is_empty(),
+            (None, Some(v)) => key.as_str() == v,
+            (None, None) => false
+        }).max_by_key(|(key, _)| if key.ends_with('*') { key.len() - 1 } else { usize::MAX }).map(|(key, entries)| (key.as_str(), entries.as_slice()))
+    }
+}
+
+// Explicit path, then signatures.json next to the config file, then the copy bundled at build time
+pub fn load_signature_database(path: Option<&Path>) -> std::io::Result<SignatureDatabase> {
+    let user_path: Option<PathBuf> = match path {
+        Some(p) => Some(p.to_path_buf()),
+        None => config_path().ok().and_then(|c| c.parent().map(|d| d.join("signatures.json"))).filter(|p| p.exists())
+    };
+    let (content, source) = match &user_path {
+        Some(p) => (std::fs::read_to_string(p)?, p.display().to_string()),
+        None => (BUNDLED_SIGNATURES.to_string(), "bundled signatures".to_string())
+    };
+    let db: SignatureDatabase = serde_json::from_str(&content).map_err(|e| Error::new(ErrorKind::InvalidData, format!("Failed to parse {}: {}", source, e)))?;
+    if db.version != SUPPORTED_VERSION { return Err(Error::new(ErrorKind::InvalidData, format!("Unsupported signature database version {} in {}!", db.version, source))); }
+    Ok(db)
+}
+
+// Scans a module image for the entry, returns the offset of the match. Resolve steps run against live memory afterwards
+// Without its section the entry is skipped, the whole image would let the pattern match inside data as well
+pub fn scan_entry(image: &MemorySnapshot, entry: &SignatureEntry) -> Option<usize> {
+    let pe = match PeImage::parse(&image.data) {
+        Ok(pe) => pe,
+        Err(e) => { eprintln!("Skipping signature {}, {}!", entry.name, e); return None; }
+    };
+    let section = match &entry.section { Some(name) => pe.section(name), None => pe.code_section() };
+    let Some(section) = section else {
+        eprintln!("Skipping signature {}, section {} not found!", entry.name, entry.section.as_deref().unwrap_or("with code"));
+        return None;
+    };
+    let (data, rva) = pe.section_data(&image.data, section);
+    // Unreadable pages are zero filled, a match touching one only matched the filler
+    let pattern = &entry.pattern;
+    let mut start = 0;
+    loop {
+        let hit = pattern.find_from(data, start)? - pattern.offset;
+        if image.is_readable(image.base + rva + hit, pattern.len()) { return Some(rva + hit + pattern.offset); }
+        start = hit + 1;
+    }
+}
@@ -0,0 +1,615 @@
+use std::collections::HashMap;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use configparser::ini::Ini;
+use rusqlite::{Connection, OptionalExtension};
+use rusqlite::types::Value as SqlValue;
+use serde::{Deserialize, Serialize};
+use winreg::enums::{HKEY_CURRENT_USER, KEY_READ, KEY_SET_VALUE};
+use winreg::{RegKey, RegValue};
+use windows::Win32::Foundation::{CloseHandle, HANDLE};
+use crate::event_helpers::{Event, EventSink};
+use crate::game_helpers::{detect_game_version, Game, Unlocker};
+use crate::hk4e_helpers::{access_denied, get_module_base, is_access_denied, get_pid_from_handle, get_process_path, wait_for_exit_or_cancel, wait_for_process, ProcessMemory, WaitOptions};
+use crate::pe_helpers::PeImage;
+use crate::policy_helpers::FpsPolicy;
+use crate::region_helpers::{enumerate_regions, MemorySnapshot, RegionQuery};
+use crate::resolve_helpers::{resolve, MemoryTarget};
+use crate::signature_helpers::{load_signature_database, scan_entry, PatchTarget, PatchValue, SignatureEntry};
+use crate::registry_helpers::{create_raw_value_from_json, find_matching_value, parse_raw_value};
+
+#[derive(Debug, Serialize, Deserialize)]
+struct MenuDataDict {
+    #[serde(rename = "___MetaType___")]
+    meta_type: String,
+    content: Vec<(i32, f64)>
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Hk4eOptions {
+    pub target_fps: u32,
+    pub refresh: Duration,
+    pub signatures: Option<PathBuf>,
+    pub wait: WaitOptions,
+    pub disable_vsync: bool,
+    pub keep_fps_unfocused: bool,
+    pub fov: Option<f32>,
+    pub policy: FpsPolicy,
+    pub events: EventSink
+}
+
+impl Hk4eOptions {
+    // FPS first, it is the only target the unlock cannot do without
+    fn targets(&self) -> Vec<PatchTarget> {
+        let mut targets = vec![PatchTarget::Fps];
+        if self.disable_vsync { targets.push(PatchTarget::Vsync); }
+        if self.keep_fps_unfocused { targets.push(PatchTarget::UnfocusedFps); }
+        if self.fov.is_some() { targets.push(PatchTarget::Fov); }
+        targets
+    }
+
+    fn patched_value(&self, target: PatchTarget, fps: u32) -> PatchValue {
+        match target {
+            PatchTarget::Fps | PatchTarget::UnfocusedFps => PatchValue::Int(fps as i32),
+            PatchTarget::Vsync => PatchValue::Int(0),
+            PatchTarget::Fov => PatchValue::Float(self.fov.unwrap_or_default())
+        }
+    }
+}
+
+// Narrower or wider than this distorts the camera badly enough to be unplayable
+const FOV_RANGE: std::ops::RangeInclusive<f32> = 30.0..=120.0;
+
+pub fn clamp_fov(fov: f32) -> f32 {
+    let clamped = fov.clamp(*FOV_RANGE.start(), *FOV_RANGE.end());
+    if clamped != fov { eprintln!("FOV {} is outside of {}-{}, using {}!", fov, FOV_RANGE.start(), FOV_RANGE.end(), clamped); }
+    clamped
+}
+
+// A value kept in place inside the game for as long as it runs
+#[derive(Debug, Clone, Copy)]
+struct Patch {
+    target: PatchTarget,
+    address: usize,
+    value: PatchValue,
+    // What the game had before the first write, restored on cancel
+    original: Option<PatchValue>
+}
+
+// Long enough for a cold start on slow disks, a stale signature set would otherwise be retried forever
+const SIGNATURE_TIMEOUT: Duration = Duration::from_secs(60);
+
+// Failures reach the event sink as well, a cancelled wait counts as a revert of nothing
+pub fn apply_hk4e(game: &Game, options: &Hk4eOptions) -> std::io::Result<()> {
+    let result = enforce_hk4e(game, options);
+    match &result {
+        // Cancelled while waiting, nothing was written so there is nothing to report
+        Err(e) if !options.wait.is_cancelled() => options.events.emit(Event::Error { message: e.to_string() }),
+        _ => {}
+    }
+    result
+}
+
+fn enforce_hk4e(game: &Game, options: &Hk4eOptions) -> std::io::Result<()> {
+    let db = load_signature_database(options.signatures.as_deref())?;
+    let target = game.executable_name();
+    println!("Waiting for {}...", target);
+    options.events.emit(Event::WaitingForProcess { executable: target.to_string() });
+    let handle = wait_for_process(target, &options.wait)?;
+    let pid = get_pid_from_handle(handle);
+    options.events.emit(Event::ProcessFound { pid });
+
+    let version = get_process_path(handle).and_then(|p| p.parent().and_then(|dir| detect_game_version(game, dir)));
+    match &version {
+        Some((v, source)) => println!("Detected {} version {} (from {})", game.name, v, source),
+        None => eprintln!("Could not detect {} version, only generic signatures will be tried!", game.name)
+    }
+    let version = version.map(|(v, _)| v);
+    // An empty set marks builds known to be incompatible, refuse instead of writing into whatever int happens to match
+    let Some((set, entries)) = db.select("hk4e", version.as_deref()).filter(|(_, entries)| !entries.is_empty()) else {
+        unsafe { CloseHandle(handle); }
+        return Err(std::io::Error::new(ErrorKind::Unsupported, format!("{} version {} is not supported!", game.name, version.unwrap_or("unknown".to_string()))));
+    };
+    println!("Using signature set \"{}\" for version {}", set, version.as_deref().unwrap_or("unknown"));
+
+    // Module list and code pages are not available right after process creation, retry until the FPS signature shows up
+    let targets = options.targets();
+    let mut addresses = HashMap::new();
+    let mut delay = Duration::ZERO;
+    let mut exited = false;
+    let deadline = Instant::now() + SIGNATURE_TIMEOUT;
+    while !addresses.contains_key(&PatchTarget::Fps) && !options.wait.is_cancelled() && Instant::now() < deadline {
+        if wait_for_exit_or_cancel(handle, delay, &options.wait) { exited = true; break; }
+        addresses = match find_signature_addresses(handle, pid, target, entries, &targets, &options.events) {
+            Ok(found) => found,
+            Err(e) => { unsafe { CloseHandle(handle); } return Err(e); }
+        };
+        delay = Duration::from_millis(1000);
+    }
+    if !addresses.contains_key(&PatchTarget::Fps) {
+        unsafe { CloseHandle(handle); }
+        // Cancelled before anything was written, nothing to restore
+        if options.wait.is_cancelled() { return Ok(()); }
+        if exited {
+            eprintln!("{} exited before FPS address was found!", game.name);
+            options.events.emit(Event::ProcessExited { pid });
+            return Ok(());
+        }
+        let tried: Vec<&str> = entries.iter().filter(|e| e.target == PatchTarget::Fps).map(|e| e.name.as_str()).collect();
+        return Err(std::io::Error::new(ErrorKind::NotFound, format!("No FPS signature matched within {}s, tried signature set \"{}\": {}!", SIGNATURE_TIMEOUT.as_secs(), set, tried.join(", "))));
+    }
+    for missing in targets.iter().filter(|t| !addresses.contains_key(t)) { eprintln!("No {} signature matched, it will not be patched!", missing.label()); }
+
+    let memory = ProcessMemory { handle };
+    let patches: Vec<Patch> = targets.iter().filter_map(|t| {
+        let address = *addresses.get(t)?;
+        Some(Patch { target: *t, address, value: options.patched_value(*t, options.target_fps), original: t.read(&memory, address).ok() })
+    }).collect();
+    let mut watchdogs: Vec<(Patch, PatchWatchdog)> = patches.into_iter().map(|p| (p, PatchWatchdog::new(options.refresh))).collect();
+    let mut result = Ok(());
+    // Waiting on the handle doubles as the refresh timer, exit is noticed right away instead of on the next tick
+    let mut delay = Duration::ZERO;
+    let mut current_fps = options.target_fps;
+    'enforce: loop {
+        if wait_for_exit_or_cancel(handle, delay, &options.wait) { exited = true; break; }
+        if options.wait.is_cancelled() { break; }
+        if !options.policy.is_static() {
+            let (fps, reason) = options.policy.target(options.target_fps, pid);
+            if fps != current_fps {
+                println!("{} FPS target changed to {} ({})", game.name, fps, reason);
+                current_fps = fps;
+            }
+        }
+        delay = Duration::MAX;
+        let mut i = 0;
+        while i < watchdogs.len() {
+            let (patch, watchdog) = &mut watchdogs[i];
+            let value = options.patched_value(patch.target, current_fps);
+            if !value.same_as(&patch.value) { patch.value = value; }
+            match watchdog.tick(&memory, patch, &options.events) {
+                Ok(next) => {
+                    // Only announce values that were read back in place
+                    if let Some(value) = watchdog.take_confirmed() { report_patched(game, patch.target, value, &options.events); }
+                    delay = delay.min(next);
+                    i += 1;
+                }
+                // Losing an optional patch is not worth giving up the FPS unlock for
+                Err(e) if patch.target != PatchTarget::Fps => { eprintln!("{}, no longer patching {}", e, patch.target.label()); watchdogs.remove(i); }
+                Err(e) => { result = Err(e); break 'enforce; }
+            }
+        }
+    }
+    for (patch, watchdog) in &watchdogs {
+        if watchdog.reverts > 0 { println!("{} reverted the {} value {} times", game.name, patch.target.label(), watchdog.reverts); }
+    }
+    if options.wait.is_cancelled() {
+        // Stopped on request while the game keeps running, hand it back its own values
+        let mut restored = false;
+        for (patch, _) in &watchdogs {
+            let Some(original) = patch.original else { continue };
+            match original.write(&memory, patch.address) {
+                Ok(()) => { println!("{} {} restored to {}", game.name, patch.target.label(), original); restored = true; }
+                Err(e) => {
+                    let message = format!("Failed to restore {} {}: {}!", game.name, patch.target.label(), e);
+                    eprintln!("{}", message);
+                    options.events.emit(Event::Error { message });
+                }
+            }
+        }
+        if restored { options.events.emit(Event::Reverted); }
+    }
+    if exited { options.events.emit(Event::ProcessExited { pid }); }
+    unsafe { CloseHandle(handle); }
+    result
+}
+
+fn report_patched(game: &Game, target: PatchTarget, value: PatchValue, events: &EventSink) {
+    match (target, value) {
+        (PatchTarget::Fps, PatchValue::Int(fps)) => { println!("{} FPS unlocked to {}", game.name, fps); events.emit(Event::Patched { fps: fps as u32 }); }
+        (PatchTarget::Fov, _) => println!("{} FOV set to {}", game.name, value),
+        _ => {}
+    }
+}
+
+// Consecutive bad ticks before the address is considered wrong, loading screens can briefly hold 0
+const MAX_BAD_TICKS: u32 = 10;
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+// Verifies every write by reading it back and backs off while the address holds values that do not belong to the patch
+struct PatchWatchdog {
+    refresh: Duration,
+    bad_ticks: u32,
+    failed_writes: u32,
+    reverts: u32,
+    // Last value read back in place and the last one reported, reporting waits for the former
+    confirmed: Option<PatchValue>,
+    reported: Option<PatchValue>
+}
+
+impl PatchWatchdog {
+    fn new(refresh: Duration) -> PatchWatchdog { PatchWatchdog { refresh, bad_ticks: 0, failed_writes: 0, reverts: 0, confirmed: None, reported: None } }
+
+    // Returns how long to wait before the next tick
+    fn tick(&mut self, memory: &impl MemoryTarget, patch: &Patch, events: &EventSink) -> std::io::Result<Duration> {
+        let (label, address, target) = (patch.target.label(), patch.address, patch.value);
+        let value = match patch.target.read(memory, address) {
+            Ok(v) if patch.target.plausible().contains(v) => v,
+            Ok(v) => return self.bad_tick(format!("{} address {:#x} holds implausible value {}", label, address, v)),
+            Err(e) => return self.bad_tick(format!("{} address {:#x} is unreadable: {}", label, address, e))
+        };
+        self.bad_ticks = 0;
+        if value.same_as(&target) { self.confirmed = Some(target); return Ok(self.refresh); }
+
+        // Only a target seen in place can be reverted, the first write, retargets and retries after failed writes are ours
+        if self.confirmed.take().is_some_and(|c| c.same_as(&target)) { self.reverts += 1; events.emit(Event::RevertedByGame { target: patch.target, value }); }
+        let written = match target.write(memory, address) {
+            Ok(()) => patch.target.read(memory, address).is_ok_and(|v| v.same_as(&target)),
+            Err(e) if is_access_denied(&e) => return Err(access_denied(format!("Writing {} value at {:#x} was denied", label, address))),
+            Err(_) => false
+        };
+        if written { self.failed_writes = 0; self.confirmed = Some(target); return Ok(self.refresh); }
+        self.failed_writes += 1;
+        if self.failed_writes >= MAX_BAD_TICKS { return Err(std::io::Error::other(format!("{} value at {:#x} does not stick, stopping!", label, address))); }
+        Ok(self.refresh)
+    }
+
+    fn take_confirmed(&mut self) -> Option<PatchValue> {
+        let confirmed = self.confirmed?;
+        if self.reported.is_some_and(|r| r.same_as(&confirmed)) { return None; }
+        self.reported = Some(confirmed);
+        Some(confirmed)
+    }
+
+    fn bad_tick(&mut self, reason: String) -> std::io::Result<Duration> {
+        self.bad_ticks += 1;
+        if self.bad_ticks >= MAX_BAD_TICKS { return Err(std::io::Error::new(ErrorKind::InvalidData, format!("{}, address looks wrong, stopping!", reason))); }
+        eprintln!("{}, backing off", reason);
+        Ok(self.refresh.saturating_mul(1 << self.bad_ticks.min(5)).min(MAX_BACKOFF))
+    }
+}
+
+// Tries the signatures of every wanted target in order, the first one per target that resolves to a plausible value wins
+fn find_signature_addresses(handle: HANDLE, pid: u32, executable: &str, entries: &[SignatureEntry], targets: &[PatchTarget], events: &EventSink) -> std::io::Result<HashMap<PatchTarget, usize>> {
+    let memory = ProcessMemory { handle };
+    let query = RegionQuery::open(pid);
+    let mut images: HashMap<String, MemorySnapshot> = HashMap::new();
+    let mut found = HashMap::new();
+    for entry in entries {
+        if !targets.contains(&entry.target) || found.contains_key(&entry.target) { continue; }
+        let module = entry.module.as_deref().unwrap_or(executable);
+        if !images.contains_key(module) {
+            let Some((base, size)) = get_module_base(pid, module) else { continue };
+            let image = MemorySnapshot::capture(handle, &query, base, size);
+            // Not a single page came back although the module is mapped, reads are being blocked
+            if image.readable_len() == 0 { return Err(access_denied(format!("Could not read {} memory", module))); }
+            events.emit(Event::ModuleBase { module: module.to_string(), base, size });
+            images.insert(module.to_string(), image);
+        }
+        let image = &images[module];
+        let Some(offset) = scan_entry(image, entry) else { continue };
+        // The snapshot covers the module, anything pointing outside of it (heap derefs) has to be read live
+        let resolved = resolve(image, image.base + offset, &entry.resolve).or_else(|| resolve(&memory, image.base + offset, &entry.resolve));
+        let Some(address) = resolved else { continue };
+        if let Some(expect) = entry.expect {
+            match entry.target.read(&memory, address) {
+                Ok(v) if expect.contains(v) => {}
+                Ok(v) => { eprintln!("Signature {} resolved to implausible value {}, trying next", entry.name, v); continue; }
+                Err(_) => { eprintln!("Signature {} resolved to an unreadable address, trying next", entry.name); continue; }
+            }
+        }
+        // Region info is missing when the query fails, the value was readable above so only a known read-only page rejects it
+        if enumerate_regions(&query, address..address + 4).first().is_some_and(|r| !r.is_writable()) { eprintln!("Signature {} resolved to read-only memory, trying next", entry.name); continue; }
+        let rva = address.wrapping_sub(image.base);
+        let location = PeImage::parse(&image.data).map(|pe| pe.describe_rva(rva)).unwrap_or(format!("{:#x}", rva));
+        println!("Signature {} matched, {} value at {}:{}", entry.name, entry.target.label(), module, location);
+        events.emit(Event::SignatureMatched { name: entry.name.clone(), target: entry.target, module: module.to_string(), location });
+        found.insert(entry.target, address);
+    }
+    Ok(found)
+}
+
+pub fn apply_hkrpg(game: &Game, target_fps: u32, events: &EventSink) -> std::io::Result<()> { report_startup(game, write_hkrpg(game, target_fps), events) }
+
+pub fn apply_bh3(game: &Game, target_fps: u32, events: &EventSink) -> std::io::Result<()> { report_startup(game, write_bh3(game, target_fps), events) }
+
+pub fn apply_wuwa(game: &Game, target_fps: u32, game_path: &str, events: &EventSink) -> std::io::Result<()> { report_startup(game, write_wuwa(game, target_fps, game_path), events) }
+
+fn open_settings(game: &Game, flags: u32) -> std::io::Result<RegKey> {
+    RegKey::predef(HKEY_CURRENT_USER).open_subkey_with_flags(game.registry_key.unwrap_or_default(), flags).map_err(|e| std::io::Error::new(e.kind(), match e.kind() {
+        ErrorKind::NotFound => "Registry container not found!",
+        ErrorKind::PermissionDenied => "Permission denied!",
+        _ => {"Something catastrophic happened!"}
+    }))
+}
+
+fn write_settings(game: &Game, name: &str, value: &RegValue) -> std::io::Result<()> {
+    open_settings(game, KEY_SET_VALUE)?.set_raw_value(name, value).map_err(|e| std::io::Error::new(e.kind(), format!("Failed to unlock {} FPS: {}!", game.name, e)))
+}
+
+// Returns the FPS that was written
+fn write_hkrpg(game: &Game, target_fps: u32) -> std::io::Result<u64> {
+    let key = open_settings(game, KEY_READ)?;
+    let available: Vec<String> = key.enum_values().filter_map(|result| result.ok().map(|(name, _)| name)).collect();
+    let setting = "GraphicsSettings_Model";
+    // TODO: Write the empty key following default values except modified fps
+    let Some(v) = find_matching_value(&available, setting) else { return Err(std::io::Error::new(ErrorKind::NotFound, "No settings found!")); };
+    let graphics_settings = key.get_raw_value(&v)?;
+    let mut pretty_settings = parse_raw_value(&graphics_settings)?;
+    if pretty_settings.get("FPS").is_none() { return Err(std::io::Error::new(ErrorKind::NotFound, "No FPS key found!")); }
+
+    if target_fps >= 120 { pretty_settings["FPS"] = serde_json::Value::Number(serde_json::Number::from(120)); } else { pretty_settings["FPS"] = serde_json::Value::Number(serde_json::Number::from(target_fps)); }
+    let updated = create_raw_value_from_json(&pretty_settings, &graphics_settings)?;
+    write_settings(game, &v, &updated)?;
+    Ok(pretty_settings["FPS"].as_u64().unwrap_or_default())
+}
+
+fn write_bh3(game: &Game, target_fps: u32) -> std::io::Result<u64> {
+    let key = open_settings(game, KEY_READ)?;
+    let available: Vec<String> = key.enum_values().filter_map(|result| result.ok().map(|(name, _)| name)).collect();
+    let setting = "PersonalGraphicsSettingV2";
+    // TODO: Write the empty key following default values except modified fps
+    let Some(v) = find_matching_value(&available, setting) else { return Err(std::io::Error::new(ErrorKind::NotFound, "No settings found!")); };
+    let graphics_settings = key.get_raw_value(&v)?;
+    let mut pretty_settings = parse_raw_value(&graphics_settings)?;
+    if pretty_settings.get("TargetFrameRateForInLevel").is_none() || pretty_settings.get("TargetFrameRateForOthers").is_none() {
+        return Err(std::io::Error::new(ErrorKind::NotFound, "No TargetFrameRateForInLevel or TargetFrameRateForOthers key found!"));
+    }
+
+    // Fallback to 60
+    if target_fps >= 300 { pretty_settings["TargetFrameRateForInLevel"] = serde_json::Value::Number(serde_json::Number::from(60)); } else { pretty_settings["TargetFrameRateForInLevel"] = serde_json::Value::Number(serde_json::Number::from(target_fps)); }
+    if target_fps >= 300 { pretty_settings["TargetFrameRateForOthers"] = serde_json::Value::Number(serde_json::Number::from(600)); } else { pretty_settings["TargetFrameRateForOthers"] = serde_json::Value::Number(serde_json::Number::from(target_fps)); }
+    let updated = create_raw_value_from_json(&pretty_settings, &graphics_settings)?;
+    write_settings(game, &v, &updated)?;
+    Ok(pretty_settings["TargetFrameRateForInLevel"].as_u64().unwrap_or_default())
+}
+
+fn wuwa_paths(game: &Game, game_path: &str) -> (PathBuf, PathBuf) {
+    let data_dir = Path::new(game_path).join(game.data_dir);
+    (data_dir.join("LocalStorage/LocalStorage.db"), data_dir.join("Config/WindowsNoEditor/GameUserSettings.ini"))
+}
+
+fn write_wuwa(game: &Game, target_fps: u32, game_path: &str) -> std::io::Result<u64> {
+    let (localstorage, gameusersettings) = wuwa_paths(game, game_path);
+    if !localstorage.exists() { return Err(std::io::Error::new(ErrorKind::NotFound, "LocalStorage does not exist!")); }
+    if !gameusersettings.exists() { return Err(std::io::Error::new(ErrorKind::NotFound, "GameUserSettings does not exist!")); }
+
+    let fpsv = if target_fps >= 120 { 120 } else { target_fps };
+
+    let mut sqlc = Connection::open(&localstorage).map_err(std::io::Error::other)?;
+    let menu_data_dict = MenuDataDict {
+        meta_type: "___Map___".to_string(),
+        content: vec![
+            (1, 100.0), (2, 100.0), (3, 100.0), (4, 100.0), (5, 0.0), (6, 0.0),
+            (7, -0.4658685302734375), (10, 3.0), (11, 3.0), (20, 0.0), (21, 0.0),
+            (22, 0.0), (23, 0.0), (24, 0.0), (25, 0.0), (26, 0.0), (27, 0.0),
+            (28, 0.0), (29, 0.0), (30, 0.0), (31, 0.0), (32, 0.0), (33, 0.0),
+            (34, 0.0), (35, 0.0), (36, 0.0), (37, 0.0), (38, 0.0), (39, 0.0),
+            (40, 0.0), (41, 0.0), (42, 0.0), (43, 0.0), (44, 0.0), (45, 0.0),
+            (46, 0.0), (47, 0.0), (48, 0.0), (49, 0.0), (50, 0.0), (51, 1.0),
+            (52, 1.0), (53, 0.0), (54, 3.0), (55, 1.0), (56, 2.0), (57, 1.0),
+            (58, 1.0), (59, 1.0), (61, 0.0), (62, 0.0), (63, 1.0), (64, 1.0),
+            (65, 0.0), (66, 0.0), (67, 3.0), (68, 2.0), (69, 100.0), (70, 100.0),
+            (79, 1.0), (81, 0.0), (82, 1.0), (83, 1.0), (84, 0.0), (85, 0.0),
+            (87, 0.0), (88, 0.0), (89, 50.0), (90, 50.0), (91, 50.0), (92, 50.0),
+            (93, 1.0), (99, 0.0), (100, 30.0), (101, 0.0), (102, 1.0),
+            (103, 0.0), (104, 50.0), (105, 0.0), (106, 0.3), (107, 0.0),
+            (112, 0.0), (113, 0.0), (114, 0.0), (115, 0.0), (116, 0.0),
+            (117, 0.0), (118, 0.0), (119, 0.0), (120, 0.0), (121, 1.0),
+            (122, 1.0), (123, 0.0), (130, 0.0), (131, 0.0), (132, 1.0),
+            (135, 1.0), (133, 0.0),
+        ],
+    };
+    let play_menu_info_dict: HashMap<&str, f64> = [
+        ("1", 100.0), ("2", 100.0), ("3", 100.0), ("4", 100.0), ("5", 0.0), ("6", 0.0),
+        ("7", -0.4658685302734375), ("10", 3.0), ("11", 3.0), ("20", 0.0), ("21", 0.0),
+        ("22", 0.0), ("23", 0.0), ("24", 0.0), ("25", 0.0), ("26", 0.0), ("27", 0.0),
+        ("28", 0.0), ("29", 0.0), ("30", 0.0), ("31", 0.0), ("32", 0.0), ("33", 0.0),
+        ("34", 0.0), ("35", 0.0), ("36", 0.0), ("37", 0.0), ("38", 0.0), ("39", 0.0),
+        ("40", 0.0), ("41", 0.0), ("42", 0.0), ("43", 0.0), ("44", 0.0), ("45", 0.0),
+        ("46", 0.0), ("47", 0.0), ("48", 0.0), ("49", 0.0), ("50", 0.0), ("51", 1.0),
+        ("52", 1.0), ("53", 0.0), ("54", 3.0), ("55", 1.0), ("56", 2.0), ("57", 1.0),
+        ("58", 1.0), ("59", 1.0), ("61", 0.0), ("62", 0.0), ("63", 1.0), ("64", 1.0),
+        ("65", 0.0), ("66", 0.0), ("67", 3.0), ("68", 2.0), ("69", 100.0), ("70", 100.0),
+        ("79", 1.0), ("81", 0.0), ("82", 1.0), ("83", 1.0), ("84", 0.0), ("85", 0.0),
+        ("87", 0.0), ("88", 0.0), ("89", 50.0), ("90", 50.0), ("91", 50.0), ("92", 50.0),
+        ("93", 1.0), ("99", 0.0), ("100", 30.0), ("101", 0.0), ("102", 1.0), ("103", 0.0),
+        ("104", 50.0), ("105", 0.0), ("106", 0.3), ("107", 0.0), ("112", 0.0), ("113", 0.0),
+        ("114", 0.0), ("115", 0.0), ("116", 0.0), ("117", 0.0), ("118", 0.0), ("119", 0.0),
+        ("120", 0.0), ("121", 1.0), ("122", 1.0), ("123", 0.0), ("130", 0.0), ("131", 0.0),
+        ("132", 1.0),
+    ].iter().cloned().collect();
+
+    sqlc.execute(&format!("DROP TRIGGER IF EXISTS {}", WUWA_TRIGGER), []).map_err(std::io::Error::other)?;
+
+    let trigger_sql = format!(r#"
+        CREATE TRIGGER {trigger}
+        AFTER UPDATE OF value ON LocalStorage
+        WHEN NEW.key = 'CustomFrameRate'
+        BEGIN
+            UPDATE LocalStorage
+            SET value = {fps}
+            WHERE key = 'CustomFrameRate';
+        END;
+        "#, trigger = WUWA_TRIGGER, fps = fpsv);
+
+    sqlc.execute(trigger_sql.as_str(), []).map_err(std::io::Error::other)?;
+    sqlc.execute("UPDATE LocalStorage SET value = ? WHERE key = 'CustomFrameRate'", [&fpsv.to_string()], ).map_err(std::io::Error::other)?;
+    sqlc.execute("DELETE FROM LocalStorage WHERE key IN ('MenuData', 'PlayMenuInfo')", [], ).map_err(std::io::Error::other)?;
+
+    let insert_records = vec![("MenuData", serde_json::to_string(&menu_data_dict)?), ("PlayMenuInfo", serde_json::to_string(&play_menu_info_dict)?)];
+    let tx = sqlc.transaction().map_err(std::io::Error::other)?;
+    {
+        let mut stmt = tx.prepare("INSERT INTO LocalStorage (key, value) VALUES (?, ?)").map_err(std::io::Error::other)?;
+        for (key, value) in &insert_records { stmt.execute((&key, &value)).map_err(std::io::Error::other)?; }
+    }
+    tx.commit().map_err(std::io::Error::other)?;
+    sqlc.close().map_err(|(_, e)| std::io::Error::other(e))?;
+
+    let mut ini = Ini::new();
+    ini.load(&gameusersettings).map_err(std::io::Error::other)?;
+    ini.set("/Script/Engine.GameUserSettings", "FramePace", Some(fpsv.to_string()));
+    ini.write(&gameusersettings).map_err(|e| std::io::Error::new(e.kind(), format!("Failed to unlock {} FPS: {}!", game.name, e)))?;
+    Ok(fpsv as u64)
+}
+
+const WUWA_TRIGGER: &str = "prevent_custom_frame_rate_update";
+
+// What a startup unlock overwrites, taken before writing so a revert puts back exactly what was there
+pub enum StartupBackup {
+    // The whole settings value, other settings stored next to the FPS come back with it
+    Registry { name: String, raw: RegValue },
+    // LocalStorage rows the unlock rewrites, a trigger the game had under the same name and the ini FramePace
+    Wuwa { rows: Vec<(String, SqlValue)>, trigger: Option<String>, frame_pace: Option<String> }
+}
+
+// RegValue is not Clone
+impl Clone for StartupBackup {
+    fn clone(&self) -> StartupBackup {
+        match self {
+            StartupBackup::Registry { name, raw } => StartupBackup::Registry { name: name.clone(), raw: RegValue { bytes: raw.bytes.clone(), vtype: raw.vtype.clone() } },
+            StartupBackup::Wuwa { rows, trigger, frame_pace } => StartupBackup::Wuwa { rows: rows.clone(), trigger: trigger.clone(), frame_pace: frame_pace.clone() }
+        }
+    }
+}
+
+pub fn backup_startup(game: &Game, game_path: &str) -> std::io::Result<StartupBackup> {
+    match game.unlocker {
+        Unlocker::Hkrpg | Unlocker::Bh3 => {
+            let key = RegKey::predef(HKEY_CURRENT_USER).open_subkey_with_flags(game.registry_key.unwrap_or_default(), KEY_READ)?;
+            let available: Vec<String> = key.enum_values().filter_map(|result| result.ok().map(|(name, _)| name)).collect();
+            let Some(name) = game.unlocker.fps_setting().and_then(|(setting, _)| find_matching_value(&available, setting)) else {
+                return Err(std::io::Error::new(ErrorKind::NotFound, format!("No {} settings found to back up!", game.name)));
+            };
+            let raw = key.get_raw_value(&name)?;
+            Ok(StartupBackup::Registry { name, raw })
+        }
+        Unlocker::Wuwa => {
+            let (localstorage, gameusersettings) = wuwa_paths(game, game_path);
+            if !localstorage.exists() { return Err(std::io::Error::new(ErrorKind::NotFound, "LocalStorage does not exist!")); }
+            let sqlc = Connection::open(&localstorage).map_err(std::io::Error::other)?;
+            let rows = {
+                let mut stmt = sqlc.prepare("SELECT key, value FROM LocalStorage WHERE key IN ('CustomFrameRate', 'MenuData', 'PlayMenuInfo')").map_err(std::io::Error::other)?;
+                stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?))).map_err(std::io::Error::other)?.collect::<Result<Vec<(String, SqlValue)>, _>>().map_err(std::io::Error::other)?
+            };
+            let trigger = sqlc.query_row("SELECT sql FROM sqlite_master WHERE type = 'trigger' AND name = ?", [WUWA_TRIGGER], |row| row.get(0)).optional().map_err(std::io::Error::other)?;
+            let mut ini = Ini::new();
+            ini.load(&gameusersettings).map_err(std::io::Error::other)?;
+            Ok(StartupBackup::Wuwa { rows, trigger, frame_pace: ini.get("/Script/Engine.GameUserSettings", "FramePace") })
+        }
+        Unlocker::Hk4e => Err(std::io::Error::new(ErrorKind::Unsupported, format!("{} is patched in memory, there is nothing to back up!", game.name)))
+    }
+}
+
+pub fn restore_startup(game: &Game, game_path: &str, backup: &StartupBackup) -> std::io::Result<()> {
+    match backup {
+        StartupBackup::Registry { name, raw } => {
+            let key = RegKey::predef(HKEY_CURRENT_USER).open_subkey_with_flags(game.registry_key.unwrap_or_default(), KEY_SET_VALUE)?;
+            key.set_raw_value(name, raw)?;
+        }
+        StartupBackup::Wuwa { rows, trigger, frame_pace } => {
+            let (localstorage, gameusersettings) = wuwa_paths(game, game_path);
+            let mut sqlc = Connection::open(&localstorage).map_err(std::io::Error::other)?;
+            let tx = sqlc.transaction().map_err(std::io::Error::other)?;
+            // Ours first, it would rewrite CustomFrameRate right after the restore
+            tx.execute(&format!("DROP TRIGGER IF EXISTS {}", WUWA_TRIGGER), []).map_err(std::io::Error::other)?;
+            tx.execute("DELETE FROM LocalStorage WHERE key IN ('CustomFrameRate', 'MenuData', 'PlayMenuInfo')", []).map_err(std::io::Error::other)?;
+            for (key, value) in rows { tx.execute("INSERT INTO LocalStorage (key, value) VALUES (?, ?)", (key, value)).map_err(std::io::Error::other)?; }
+            if let Some(sql) = trigger { tx.execute(sql, []).map_err(std::io::Error::other)?; }
+            tx.commit().map_err(std::io::Error::other)?;
+
+            let mut ini = Ini::new();
+            ini.load(&gameusersettings).map_err(std::io::Error::other)?;
+            match frame_pace {
+                Some(value) => { ini.set("/Script/Engine.GameUserSettings", "FramePace", Some(value.clone())); }
+                None => { ini.remove_key("/Script/Engine.GameUserSettings", "FramePace"); }
+            }
+            ini.write(&gameusersettings)?;
+        }
+    }
+    println!("{} settings restored", game.name);
+    Ok(())
+}
+
+// Startup unlocks either report the written FPS or fail with an error event
+fn report_startup(game: &Game, result: std::io::Result<u64>, events: &EventSink) -> std::io::Result<()> {
+    match result {
+        Ok(fps) => { println!("{} FPS unlocked to {}", game.name, fps); events.emit(Event::Patched { fps: fps as u32 }); Ok(()) }
+        Err(e) => { events.emit(Event::Error { message: e.to_string() }); Err(e) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use crate::resolve_helpers::MemorySource;
+
+    const ADDRESS: usize = 0x1000;
+
+    // Single FPS value, writes are dropped while stuck like a game resetting it right away
+    struct FakeMemory {
+        fps: Cell<i32>,
+        stuck: Cell<bool>
+    }
+
+    impl MemorySource for FakeMemory {
+        fn read_bytes(&self, address: usize, buffer: &mut [u8]) -> std::io::Result<()> {
+            if address != ADDRESS || buffer.len() != 4 { return Err(std::io::Error::other("unmapped")); }
+            buffer.copy_from_slice(&self.fps.get().to_le_bytes());
+            Ok(())
+        }
+    }
+
+    impl MemoryTarget for FakeMemory {
+        fn write_bytes(&self, _address: usize, bytes: &[u8]) -> windows::core::Result<()> {
+            if !self.stuck.get() { self.fps.set(i32::from_le_bytes(bytes.try_into().unwrap())); }
+            Ok(())
+        }
+    }
+
+    fn fps_patch(fps: i32) -> Patch { Patch { target: PatchTarget::Fps, address: ADDRESS, value: PatchValue::Int(fps), original: Some(PatchValue::Int(60)) } }
+
+    #[test]
+    fn watchdog_counts_only_game_reverts() {
+        let memory = FakeMemory { fps: Cell::new(60), stuck: Cell::new(false) };
+        let events = EventSink::default();
+        let mut watchdog = PatchWatchdog::new(Duration::from_secs(1));
+        let mut patch = fps_patch(144);
+
+        // The first write replaces the game's own value, that is no revert
+        watchdog.tick(&memory, &patch, &events).unwrap();
+        assert_eq!((memory.fps.get(), watchdog.reverts), (144, 0));
+        assert_eq!(watchdog.take_confirmed(), Some(PatchValue::Int(144)));
+        watchdog.tick(&memory, &patch, &events).unwrap();
+        assert_eq!(watchdog.reverts, 0);
+        assert_eq!(watchdog.take_confirmed(), None);
+
+        memory.fps.set(60);
+        watchdog.tick(&memory, &patch, &events).unwrap();
+        assert_eq!((memory.fps.get(), watchdog.reverts), (144, 1));
+
+        // A policy change is ours as well
+        patch.value = PatchValue::Int(30);
+        watchdog.tick(&memory, &patch, &events).unwrap();
+        assert_eq!((memory.fps.get(), watchdog.reverts), (30, 1));
+        assert_eq!(watchdog.take_confirmed(), Some(PatchValue::Int(30)));
+    }
+
+    #[test]
+    fn watchdog_failed_writes_are_not_reverts() {
+        let memory = FakeMemory { fps: Cell::new(144), stuck: Cell::new(false) };
+        let events = EventSink::default();
+        let mut watchdog = PatchWatchdog::new(Duration::from_secs(1));
+        let patch = fps_patch(144);
+        watchdog.tick(&memory, &patch, &events).unwrap();
+
+        // One revert, then every retry fails until the watchdog gives up
+        memory.fps.set(60);
+        memory.stuck.set(true);
+        for _ in 1..MAX_BAD_TICKS { watchdog.tick(&memory, &patch, &events).unwrap(); }
+        assert_eq!(watchdog.reverts, 1);
+        assert!(watchdog.tick(&memory, &patch, &events).is_err());
+        assert_eq!(watchdog.reverts, 1);
+    }
+}
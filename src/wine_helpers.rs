@@ -0,0 +1,200 @@
+use std::path::{Path, PathBuf};
+use windows::core::PCSTR;
+use windows::Win32::System::LibraryLoader::{GetModuleHandleA, GetProcAddress};
+
+#[derive(Debug, Clone)]
+pub enum RegFileValue {
+    String(String),
+    Binary(Vec<u8>)
+}
+
+#[derive(Debug, Default)]
+pub struct RegFileKey {
+    pub path: String,
+    pub values: Vec<(String, RegFileValue)>
+}
+
+impl RegFileKey {
+    pub fn get(&self, name: &str) -> Option<&RegFileValue> { self.values.iter().find(|(n, _)| n.eq_ignore_ascii_case(name)).map(|(_, v)| v) }
+
+    pub fn get_string(&self, name: &str) -> Option<String> {
+        match self.get(name)? {
+            RegFileValue::String(s) => Some(s.clone()),
+            _ => None
+        }
+    }
+}
+
+pub fn is_wine() -> bool {
+    unsafe {
+        match GetModuleHandleA(PCSTR(c"ntdll.dll".as_ptr().cast())) {
+            Ok(ntdll) => GetProcAddress(ntdll, PCSTR(c"wine_get_version".as_ptr().cast())).is_some(),
+            Err(_) => false
+        }
+    }
+}
+
+// Wine exposes the host filesystem on Z:, plain unix paths would resolve against the current drive instead
+pub fn unix_path(path: &str) -> PathBuf {
+    if is_wine() && path.starts_with('/') { PathBuf::from(format!("Z:{}", path.replace('/', "\\"))) } else { PathBuf::from(path) }
+}
+
+// XDG base directory such as XDG_DATA_HOME with its fallback under $HOME (set by Wine to the unix home), %APPDATA% on Windows.
+// An empty variable counts as unset like the spec says
+pub fn xdg_dir(var: &str, home_fallback: &str) -> Option<PathBuf> {
+    match (std::env::var(var), std::env::var("HOME"), std::env::var("APPDATA")) {
+        (Ok(xdg), _, _) if !xdg.is_empty() => Some(unix_path(&xdg)),
+        (_, Ok(home), _) if home.starts_with('/') => Some(unix_path(&home).join(home_fallback)),
+        (_, _, Ok(appdata)) => Some(PathBuf::from(appdata)),
+        _ => None
+    }
+}
+
+// Yama restricts ptrace to descendants or admins, wineserver needs it to touch another process's memory
+pub fn ptrace_scope_hint() -> Option<String> {
+    if !is_wine() { return None; }
+    let scope = std::fs::read_to_string(unix_path("/proc/sys/kernel/yama/ptrace_scope")).ok()?;
+    match scope.trim() {
+        "1" => Some("kernel.yama.ptrace_scope is 1, only parent processes can access memory. Launch the game through `keqing_unlock launch` or run `sudo sysctl kernel.yama.ptrace_scope=0`".to_string()),
+        "2" => Some("kernel.yama.ptrace_scope is 2, only processes with CAP_SYS_PTRACE can access memory. Run `sudo sysctl kernel.yama.ptrace_scope=0`".to_string()),
+        "3" => Some("kernel.yama.ptrace_scope is 3, memory access is disabled until reboot".to_string()),
+        _ => None
+    }
+}
+
+// Maps a path stored in the prefix registry (C:\Program Files\...) to the prefix on disk
+pub fn prefix_path(prefix: &Path, windows_path: &str) -> Option<PathBuf> {
+    let mut chars = windows_path.chars();
+    let drive = chars.next()?.to_ascii_lowercase();
+    if chars.next()? != ':' || !drive.is_ascii_alphabetic() { return None; }
+    let rest = windows_path[2..].trim_start_matches(['\\', '/']).replace('\\', "/");
+    let root = if drive == 'c' { prefix.join("drive_c") } else { prefix.join("dosdevices").join(format!("{}:", drive)) };
+    Some(if rest.is_empty() { root } else { root.join(rest) })
+}
+
+pub fn parse_reg_file(path: &Path) -> std::io::Result<Vec<RegFileKey>> { Ok(parse_reg(&std::fs::read_to_string(path)?)) }
+
+fn parse_reg(content: &str) -> Vec<RegFileKey> {
+    let mut keys: Vec<RegFileKey> = Vec::new();
+    let mut pending = String::new();
+
+    for line in content.lines() {
+        // hex values are wrapped across lines with a trailing backslash
+        if let Some(stripped) = line.strip_suffix('\\') { pending.push_str(stripped.trim_start()); continue; }
+        let line = if pending.is_empty() { line.to_string() } else { let full = format!("{}{}", pending, line.trim_start()); pending.clear(); full };
+
+        if line.starts_with('[') {
+            let end = line.rfind(']').unwrap_or(line.len());
+            keys.push(RegFileKey { path: unescape(&line[1..end]), values: Vec::new() });
+        } else if line.starts_with('"') || line.starts_with('@') {
+            let Some(key) = keys.last_mut() else { continue };
+            let (name, rest) = if let Some(rest) = line.strip_prefix('@') { (String::new(), rest) } else {
+                let end = find_closing_quote(&line[1..]).map(|i| i + 1).unwrap_or(line.len());
+                (unescape(&line[1..end]), line.get(end + 1..).unwrap_or_default())
+            };
+            let Some(data) = rest.strip_prefix('=') else { continue };
+            if let Some(value) = parse_reg_data(data) { key.values.push((name, value)); }
+        }
+    }
+    keys
+}
+
+pub fn find_reg_key<'a>(keys: &'a [RegFileKey], path: &str) -> Option<&'a RegFileKey> { keys.iter().find(|k| k.path.eq_ignore_ascii_case(path)) }
+
+fn parse_reg_data(data: &str) -> Option<RegFileValue> {
+    if let Some(s) = data.strip_prefix('"') {
+        let end = find_closing_quote(s).unwrap_or(s.len());
+        return Some(RegFileValue::String(unescape(&s[..end])));
+    }
+    if data.starts_with("hex") {
+        let hex = &data[data.find(':')? + 1..];
+        let bytes: Vec<u8> = hex.split(',').map(|b| b.trim()).filter(|b| !b.is_empty()).filter_map(|b| u8::from_str_radix(b, 16).ok()).collect();
+        return Some(RegFileValue::Binary(bytes));
+    }
+    None
+}
+
+fn find_closing_quote(s: &str) -> Option<usize> {
+    let mut escaped = false;
+    for (i, c) in s.char_indices() {
+        if escaped { escaped = false; continue; }
+        if c == '\\' { escaped = true; } else if c == '"' { return Some(i); }
+    }
+    None
+}
+
+// Wine escapes backslashes, quotes and any non-ASCII character (\x + up to 4 hex digits)
+fn unescape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\\' { out.push(c); continue; }
+        match chars.next() {
+            Some('n') => out.push('\n'),
+            Some('r') => out.push('\r'),
+            Some('t') => out.push('\t'),
+            Some('0') => out.push('\0'),
+            Some('x') => {
+                let mut code = String::new();
+                while code.len() < 4 { match chars.peek() { Some(h) if h.is_ascii_hexdigit() => { code.push(*h); chars.next(); } _ => break } }
+                if let Some(ch) = u32::from_str_radix(&code, 16).ok().and_then(char::from_u32) { out.push(ch); }
+            }
+            Some(other) => out.push(other),
+            None => out.push('\\')
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const USER_REG: &str = r#"WINE REGISTRY Version 2
+;; All keys relative to \\User\\S-1-5-21-0-0-0-1000
+
+#time=1d9a1b2c3d4e5f6
+[Software\\miHoYo\\Genshin Impact] 1700000000
+#time=1d9a1b2c3d4e5f6
+"GENERAL_DATA_h2389025596"=hex:7b,22,66,70,73,22,3a,36,30,7d,00
+"WINDOWS_MODE_h3981614963"=dword:00000000
+"Path"="C:\\Program Files\\Genshin Impact"
+@="default"
+
+[Software\\miHoYo\\\x5d29\x574f\xff1a\x661f\x7a79\x94c1\x9053] 1700000000
+"GraphicsSettings_Model_h2986158309"=hex:7b,22,46,50,53,22,3a,31,\
+  32,30,7d,\
+  00
+"Quote \"name\""="tab\tend"
+"#;
+
+    #[test]
+    fn parses_keys_and_values() {
+        let keys = parse_reg(USER_REG);
+        assert_eq!(keys.len(), 2);
+        let genshin = find_reg_key(&keys, "software\\mihoyo\\genshin impact").unwrap();
+        assert!(matches!(genshin.get("GENERAL_DATA_h2389025596"), Some(RegFileValue::Binary(b)) if b.as_slice() == b"{\"fps\":60}\0"));
+        assert_eq!(genshin.get_string("path").as_deref(), Some("C:\\Program Files\\Genshin Impact"));
+        assert_eq!(genshin.get_string("").as_deref(), Some("default"));
+        // dword values are not needed and skipped
+        assert!(genshin.get("WINDOWS_MODE_h3981614963").is_none());
+    }
+
+    #[test]
+    fn joins_continuation_lines_and_unescapes() {
+        let keys = parse_reg(USER_REG);
+        let starrail = find_reg_key(&keys, "Software\\miHoYo\\崩坏：星穹铁道").unwrap();
+        assert!(matches!(starrail.get("GraphicsSettings_Model_h2986158309"), Some(RegFileValue::Binary(b)) if b.as_slice() == b"{\"FPS\":120}\0"));
+        assert_eq!(starrail.get_string("Quote \"name\"").as_deref(), Some("tab\tend"));
+    }
+
+    #[test]
+    fn unescapes_wine_sequences() {
+        assert_eq!(unescape(r"C:\\Games\\x"), r"C:\Games\x");
+        assert_eq!(unescape(r"\x539f\x795e"), "原神");
+        // Short escapes stop at the first non hex digit
+        assert_eq!(unescape(r"\x41z"), "Az");
+        assert_eq!(unescape(r"a\nb\\"), "a\nb\\");
+        assert_eq!(unescape("trailing\\"), "trailing\\");
+    }
+}